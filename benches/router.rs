@@ -0,0 +1,178 @@
+//! Compares the segment tree router with the regex scan it replaced.
+//!
+//! Run with `cargo bench --bench router`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lib_shared::request::{Method, Request};
+use lib_shared::response::Response;
+use orangutan::converter::Converters;
+use orangutan::route::{Route, Router, Segment};
+use regex::Regex;
+
+const ROUNDS: usize = 200_000;
+
+fn handler(_request: &Request) -> Response {
+    Response::new()
+}
+
+/// The paths of a mid-sized API, with the kinds of parameters the router supports.
+fn paths() -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+
+    for resource in ["users", "teams", "projects", "invoices", "orders", "products", "reviews", "tags"] {
+        paths.push(format!("/api/{}", resource));
+        paths.push(format!("/api/{}/<int:id>", resource));
+        paths.push(format!("/api/{}/<int:id>/history", resource));
+        paths.push(format!("/api/{}/<int:id>/comments/<uint:comment>", resource));
+        paths.push(format!("/api/{}/search/<str:query>", resource));
+    }
+
+    paths.push(String::from("/"));
+    paths.push(String::from("/about"));
+    paths.push(String::from("/blog/<str:slug>"));
+    paths.push(String::from("/prices/<float:amount>"));
+    paths.push(String::from("/static/<path:file>"));
+
+    paths
+}
+
+fn requests() -> Vec<&'static str> {
+    vec![
+        "/",
+        "/about",
+        "/api/users",
+        "/api/users/42",
+        "/api/tags/7/comments/3",
+        "/api/reviews/search/rust",
+        "/blog/hello-world",
+        "/prices/9.99",
+        "/static/css/site/main.css",
+        "/nothing/here",
+    ]
+}
+
+/// The previous design: one regex per route, tried in order until one matches.
+struct RegexScan {
+    routes: Vec<(Regex, Vec<String>, Method)>,
+    cache:  HashMap<(String, Method), usize>,
+}
+
+impl RegexScan {
+    fn new(routes: &[Route]) -> RegexScan {
+        let routes = routes.iter().map(|route| {
+            let mut matcher = String::from("^");
+            let mut names = Vec::new();
+
+            for segment in &route.segments {
+                match segment {
+                    Segment::Static(part)        => matcher.push_str(&format!("/{}", regex::escape(part))),
+                    Segment::Param(param)        => {
+                        let pattern = match param.converter.as_str() {
+                            "int"   => r"-*[0-9]+",
+                            "uint"  => r"[0-9]+",
+                            "float" => r"-*[0-9]*[.]?[0-9]+",
+                            "path"  => r".+",
+                            _       => r"(?:[^/])+",
+                        };
+
+                        matcher.push_str(&format!("/(?P<{}>{})", param.name, pattern));
+                        names.push(param.name.clone());
+                    },
+                }
+            }
+
+            matcher.push_str("/?$");
+
+            (Regex::new(&matcher).unwrap(), names, route.method.clone())
+        }).collect();
+
+        RegexScan { routes, cache: HashMap::new() }
+    }
+
+    fn find(&self, path: &str, method: &Method) -> Option<HashMap<String, String>> {
+        let (matcher, names, _) = self.routes.iter().find(|(matcher, _, m)| m == method && matcher.is_match(path))?;
+        let caps = matcher.captures(path)?;
+
+        Some(names.iter().map(|name| (name.clone(), caps[name.as_str()].to_string())).collect())
+    }
+
+    /// The scan with the per-path cache it used to keep. Every new path adds an entry.
+    fn find_cached(&mut self, path: &str, method: &Method) -> Option<HashMap<String, String>> {
+        let key = (path.to_string(), method.clone());
+
+        let index = match self.cache.get(&key) {
+            Some(&index) => index,
+            None         => {
+                let index = self.routes.iter().position(|(matcher, _, m)| m == method && matcher.is_match(path))?;
+                self.cache.insert(key, index);
+                index
+            },
+        };
+
+        let (matcher, names, _) = &self.routes[index];
+        let caps = matcher.captures(path)?;
+
+        Some(names.iter().map(|name| (name.clone(), caps[name.as_str()].to_string())).collect())
+    }
+}
+
+fn measure(name: &str, rounds: usize, mut run: impl FnMut(usize)) {
+    let start = Instant::now();
+
+    for i in 0..rounds {
+        run(i);
+    }
+
+    let elapsed: Duration = start.elapsed();
+    println!("{:<32} {:>8.0} ns/lookup", name, elapsed.as_nanos() as f64 / rounds as f64);
+}
+
+fn main() {
+    let converters = Converters::default();
+
+    let routes: Vec<Route> = paths().iter()
+                                    .map(|path| Route::new(path, Method::GET, handler, &converters).unwrap())
+                                    .collect();
+
+    let mut scan = RegexScan::new(&routes);
+    let mut router = Router::new();
+
+    for route in routes {
+        router.insert(route);
+    }
+
+    let requests = requests();
+    let get = Method::GET;
+
+    println!("{} routes, {} lookups each\n", router.routes().len(), ROUNDS);
+
+    measure("tree", ROUNDS, |i| {
+        black_box(router.find(black_box(requests[i % requests.len()]), &get).map(|(_, params)| params));
+    });
+
+    measure("regex scan", ROUNDS, |i| {
+        black_box(scan.find(black_box(requests[i % requests.len()]), &get));
+    });
+
+    measure("regex scan, cached", ROUNDS, |i| {
+        black_box(scan.find_cached(black_box(requests[i % requests.len()]), &get));
+    });
+
+    // every id is a new path: the tree doesn't care, the cache grows with each of them
+    let distinct: Vec<String> = (0..ROUNDS).map(|i| format!("/api/users/{}/history", i)).collect();
+
+    measure("tree, distinct paths", ROUNDS, |i| {
+        black_box(router.find(black_box(&distinct[i]), &get).map(|(_, params)| params));
+    });
+
+    scan.cache.clear();
+
+    measure("regex scan, distinct paths", ROUNDS, |i| {
+        black_box(scan.find_cached(black_box(&distinct[i]), &get));
+    });
+
+    println!("\nregex cache entries after distinct paths: {}", scan.cache.len());
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+/// A cookie to send with `Response::set_cookie`, built like the OrangutanBuilder:
+///
+/// ```
+/// # use lib_shared::cookie::{Cookie, SameSite};
+/// # use std::time::Duration;
+/// let cookie = Cookie::new("session", "abc123")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .secure(true)
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// ```
+///
+/// The name and value can contain any characters, the ones cookies can't hold are percent-encoded
+/// and `Request::cookies` decodes them again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name:      String,
+    pub value:     String,
+    pub path:      Option<String>,
+    pub domain:    Option<String>,
+    pub max_age:   Option<Duration>,
+    pub expires:   Option<DateTime<Utc>>,
+    pub secure:    bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+/// Whether the cookie is sent with requests that come from other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only with requests from the same site.
+    Strict,
+    /// Also when following a link from another site.
+    Lax,
+    /// With every request. Browsers only accept this for Secure cookies.
+    None,
+}
+
+impl Cookie {
+    /// A cookie that lasts until the browser is closed, for the path it was set on.
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name:      String::from(name),
+            value:     String::from(value),
+            path:      None,
+            domain:    None,
+            max_age:   None,
+            expires:   None,
+            secure:    false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// The paths the cookie is sent to: `/` for the whole site.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(String::from(path));
+        self
+    }
+
+    /// The domain the cookie is sent to, including its subdomains.
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(String::from(domain));
+        self
+    }
+
+    /// How long the cookie is kept. Wins over `expires` in browsers that understand both.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// When the cookie is removed.
+    pub fn expires(mut self, expires: DateTime<Utc>) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Hide the cookie from JavaScript.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Makes the cookie one that removes the cookie with the same name, path and domain from the client.
+    pub fn expire(self) -> Self {
+        self.max_age(Duration::ZERO).expires(Utc.timestamp_opt(0, 0).unwrap())
+    }
+}
+
+/// The value of the Set-Cookie header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", encode(&self.name), encode(&self.value))?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", attribute(path))?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", attribute(domain))?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", expires.format("%a, %d %b %Y %H:%M:%S GMT"))?;
+        }
+
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax)    => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None)   => write!(f, "; SameSite=None"),
+            None                   => Ok(()),
+        }
+    }
+}
+
+/// Reads the `name=value; name2=value2` pairs of a Cookie header. When a name is there twice the first one is kept,
+/// which is the one with the longest path.
+pub(crate) fn parse(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+
+    for pair in header.split(';') {
+        if let Some((name, value)) = pair.split_once('=') {
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+
+            cookies.entry(decode(name.trim())).or_insert_with(|| decode(value));
+        }
+    }
+
+    cookies
+}
+
+/// Percent-encodes what isn't allowed in a cookie name or value: controls, spaces, `"`, `,`, `;`, `\`, `%` and non-ASCII.
+fn encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+
+    for byte in input.bytes() {
+        match byte {
+            b'!' | b'#'..=b'$' | b'&'..=b'+' | b'-'..=b':' | b'<'..=b'[' | b']'..=b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn decode(input: &str) -> String {
+    crate::request::percent_decode(input, false)
+}
+
+/// Path and Domain can't contain `;` or line breaks, which would end the attribute or the header.
+fn attribute(value: &str) -> String {
+    value.chars().filter(|c| *c != ';' && !c.is_control()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_names_and_values_parse_back() {
+        for (name, value) in [("session", "abc123"), ("a b", "x;y,z"), ("quote\"", "back\\slash 100%"), ("unicode", "ørangutan 🦧")] {
+            let set = Cookie::new(name, value).to_string();
+            let sent = set.split(';').next().unwrap();
+
+            assert!(!sent.contains([' ', '"', ',', '\\']), "{:?}", sent);
+            assert_eq!(parse(sent).get(name).map(String::as_str), Some(value), "{:?}", sent);
+        }
+    }
+
+    #[test]
+    fn header_pairs_are_trimmed_and_quotes_removed() {
+        let cookies = parse(r#" theme = dark ;quoted="a b"; empty=; ignored; encoded=%3Bx"#);
+
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies["theme"], "dark");
+        assert_eq!(cookies["quoted"], "a b");
+        assert_eq!(cookies["empty"], "");
+        assert_eq!(cookies["encoded"], ";x");
+    }
+
+    #[test]
+    fn first_of_a_duplicated_name_is_kept() {
+        let cookies = parse("id=specific; other=1; id=general");
+
+        assert_eq!(cookies["id"], "specific");
+        assert_eq!(cookies["other"], "1");
+    }
+
+    #[test]
+    fn set_cookie_has_each_attribute() {
+        let expires = Utc.with_ymd_and_hms(2030, 1, 2, 3, 4, 5).unwrap();
+
+        assert_eq!(Cookie::new("a", "1").to_string(), "a=1");
+        assert_eq!(Cookie::new("a", "1").path("/app").to_string(), "a=1; Path=/app");
+        assert_eq!(Cookie::new("a", "1").domain("example.com").to_string(), "a=1; Domain=example.com");
+        assert_eq!(Cookie::new("a", "1").max_age(Duration::from_secs(3600)).to_string(), "a=1; Max-Age=3600");
+        assert_eq!(Cookie::new("a", "1").expires(expires).to_string(), "a=1; Expires=Wed, 02 Jan 2030 03:04:05 GMT");
+        assert_eq!(Cookie::new("a", "1").secure(true).to_string(), "a=1; Secure");
+        assert_eq!(Cookie::new("a", "1").http_only(true).to_string(), "a=1; HttpOnly");
+        assert_eq!(Cookie::new("a", "1").same_site(SameSite::Strict).to_string(), "a=1; SameSite=Strict");
+        assert_eq!(Cookie::new("a", "1").same_site(SameSite::Lax).to_string(), "a=1; SameSite=Lax");
+        assert_eq!(Cookie::new("a", "1").same_site(SameSite::None).to_string(), "a=1; SameSite=None");
+
+        assert_eq!(Cookie::new("a", "1").path("/").domain("example.com").max_age(Duration::from_secs(60))
+                                        .secure(true).http_only(true).same_site(SameSite::Lax).to_string(),
+                   "a=1; Path=/; Domain=example.com; Max-Age=60; Secure; HttpOnly; SameSite=Lax");
+    }
+
+    #[test]
+    fn attributes_cant_end_the_header() {
+        let cookie = Cookie::new("a", "1").path("/x; Secure").domain("evil\r\nSet-Cookie: b=2");
+
+        assert_eq!(cookie.to_string(), "a=1; Path=/x Secure; Domain=evilSet-Cookie: b=2");
+    }
+
+    #[test]
+    fn expired_cookie_is_in_the_past() {
+        assert_eq!(Cookie::new("a", "").path("/").expire().to_string(), "a=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+}
@@ -6,6 +6,9 @@ use crate::response::Response;
 use crate::request::Request;
 
 pub mod request;
+pub mod multipart;
+pub mod cookie;
+pub mod session;
 pub mod utils;
 pub mod response;
 
@@ -138,6 +141,43 @@ pub fn is_html(payload: String) -> bool {
     re.is_match(&payload) || re_full.is_match(&payload)
 }
 
+/// A `<converter:name>` segment of a route path, like `<uint:id>` or `<any(json,xml):format>`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Placeholder {
+    pub converter: String,
+    pub args:      Vec<String>,
+    pub name:      String,
+}
+
+/// returns the Placeholder in a segment of a route path, or None if the segment is fixed text.
+/// A placeholder without a converter, like `<name>`, is a str.
+pub fn placeholder(segment: &str) -> Option<Placeholder> {
+    lazy_static! {
+        static ref PLACEHOLDER: Regex = Regex::new(r"^<(?:([a-zA-Z_][a-zA-Z0-9_]*)(?:\(([^)]*)\))?:)?([\w_][a-zA-Z0-9_]*)>$").unwrap();
+    }
+
+    let caps = PLACEHOLDER.captures(segment)?;
+
+    let args = caps.get(2).map_or(Vec::new(), |args| {
+        args.as_str()
+            .split(',')
+            .map(|arg| arg.trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+            .filter(|arg| !arg.is_empty())
+            .collect()
+    });
+
+    Some(Placeholder {
+        converter: caps.get(1).map_or("str", |x| x.as_str()).to_string(),
+        args,
+        name: caps[3].to_string(),
+    })
+}
+
+/// returns the Placeholders of a route path, from left to right.
+pub fn placeholders(path: &str) -> Vec<Placeholder> {
+    path.split('/').filter_map(placeholder).collect()
+}
+
 /// contains some important info about the Route. Arguably useless
 pub struct RouteInfo {    
     pub path: String,
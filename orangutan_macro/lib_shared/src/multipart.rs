@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::request::QueryArg;
+
+/// How much of a multipart/form-data body is accepted, and when files are written to disk instead of kept in memory.
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    /// The most parts, fields and files together, a body can have.
+    pub max_parts:     usize,
+    /// The most bytes the content of a single part can have.
+    pub max_part_size: usize,
+    /// Files larger than this are written to a temporary file, so they don't stay in memory as long as the upload is kept.
+    /// The whole body has already been read into `Request::body` by then, so this doesn't lower how much memory a request takes at most,
+    /// `OrangutanBuilder::max_body_size` does.
+    pub memory_limit:  usize,
+    /// Where the temporary files go. Defaults to `std::env::temp_dir()`.
+    pub temp_dir:      Option<PathBuf>,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            max_parts:     128,
+            max_part_size: 16 * 1024 * 1024,
+            memory_limit:  256 * 1024,
+            temp_dir:      None,
+        }
+    }
+}
+
+/// The fields and files of a multipart/form-data body, by the name they were sent with.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    /// The parts without a filename, read as text.
+    pub fields: HashMap<String, QueryArg>,
+    /// The parts with a filename, in the order they were sent. A name can have more than one, like `<input type="file" multiple>`.
+    pub files:  HashMap<String, Vec<UploadedFile>>,
+}
+
+impl Multipart {
+    /// returns the first file sent with the name.
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.get(name).and_then(|files| files.first())
+    }
+}
+
+/// A file from a multipart/form-data body. A file that was written to a temporary file is removed when the UploadedFile is dropped.
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// The name of the file on the client, without the directories some browsers send.
+    pub filename:     String,
+    /// The content type the client gave, application/octet-stream if it gave none.
+    pub content_type: String,
+    data:             Data,
+    size:             usize,
+}
+
+#[derive(Debug)]
+enum Data {
+    Memory(Vec<u8>),
+    Disk(PathBuf),
+}
+
+impl UploadedFile {
+    /// returns the size of the file in bytes.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// returns the temporary file the content was written to, or None if it is in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            Data::Memory(_)  => None,
+            Data::Disk(path) => Some(path),
+        }
+    }
+
+    /// returns the content of the file, reading it from disk if it was spilled there.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            Data::Memory(bytes) => Ok(bytes.clone()),
+            Data::Disk(path)    => fs::read(path),
+        }
+    }
+
+    /// returns a reader over the content of the file.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        match &self.data {
+            Data::Memory(bytes) => Ok(Box::new(io::Cursor::new(bytes.clone()))),
+            Data::Disk(path)    => Ok(Box::new(File::open(path)?)),
+        }
+    }
+
+    /// Writes the file to `path`. The temporary file, if there is one, is still removed on drop.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        match &self.data {
+            Data::Memory(bytes) => fs::write(path, bytes),
+            Data::Disk(temp)    => fs::copy(temp, path).map(|_| ()),
+        }
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Data::Disk(path) = &self.data {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Why a multipart/form-data body couldn't be read.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The content-type of the request isn't multipart/form-data.
+    NotMultipart,
+    /// The content-type has no boundary parameter.
+    MissingBoundary,
+    /// The body doesn't follow the multipart format, like a part without a content-disposition or a missing closing boundary.
+    Malformed(&'static str),
+    /// The body has more parts than `MultipartLimits::max_parts`.
+    TooManyParts,
+    /// A part is larger than `MultipartLimits::max_part_size`. Has the name of the part.
+    PartTooLarge(String),
+    /// A temporary file couldn't be written.
+    Io(io::Error),
+}
+
+impl MultipartError {
+    /// The status the client is answered with.
+    pub fn status(&self) -> u16 {
+        match self {
+            MultipartError::TooManyParts | MultipartError::PartTooLarge(_) => 413,
+            MultipartError::Io(_)                                           => 500,
+            _                                                               => 400,
+        }
+    }
+}
+
+impl std::fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultipartError::NotMultipart       => write!(f, "the body isn't multipart/form-data"),
+            MultipartError::MissingBoundary    => write!(f, "the content-type has no boundary"),
+            MultipartError::Malformed(problem) => write!(f, "malformed multipart body: {}", problem),
+            MultipartError::TooManyParts       => write!(f, "the body has too many parts"),
+            MultipartError::PartTooLarge(name) => write!(f, "part {:?} is too large", name),
+            MultipartError::Io(e)              => write!(f, "couldn't store an uploaded file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(e: io::Error) -> MultipartError {
+        MultipartError::Io(e)
+    }
+}
+
+/// returns the boundary parameter of a multipart/form-data content-type, or None for any other content-type.
+pub(crate) fn boundary(content_type: &str) -> Option<Result<String, MultipartError>> {
+    let mut params = content_type.split(';');
+
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    let boundary = params.filter_map(|param| param.split_once('='))
+                         .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+                         .map(|(_, value)| value.trim().trim_matches('"').to_string())
+                         .filter(|value| !value.is_empty() && value.len() <= 70);
+
+    Some(boundary.ok_or(MultipartError::MissingBoundary))
+}
+
+/// Splits `body` into its parts, as delimited by `boundary`.
+pub(crate) fn parse(body: &[u8], boundary: &str, limits: &MultipartLimits) -> Result<Multipart, MultipartError> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let next_part = format!("\r\n--{}", boundary).into_bytes();
+
+    let mut multipart = Multipart::default();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut parts = 0;
+
+    // anything before the first boundary is a preamble that is ignored
+    let mut position = find(body, &delimiter, 0).ok_or(MultipartError::Malformed("no boundary in the body"))? + delimiter.len();
+
+    loop {
+        let rest = &body[position..];
+
+        if rest.starts_with(b"--") {
+            break;
+        }
+
+        if !rest.starts_with(b"\r\n") {
+            return Err(MultipartError::Malformed("boundary isn't followed by a line break"));
+        }
+
+        parts += 1;
+
+        if parts > limits.max_parts {
+            return Err(MultipartError::TooManyParts);
+        }
+
+        let head_start = position + 2;
+        let head_end = find(body, b"\r\n\r\n", head_start).ok_or(MultipartError::Malformed("part has no end of headers"))?;
+        let content_end = find(body, &next_part, head_end + 4).ok_or(MultipartError::Malformed("no closing boundary"))?;
+
+        let head = String::from_utf8_lossy(&body[head_start..head_end]);
+        let content = &body[head_end + 4..content_end];
+
+        let (name, filename, content_type) = part_headers(&head)?;
+
+        if content.len() > limits.max_part_size {
+            return Err(MultipartError::PartTooLarge(name));
+        }
+
+        match filename {
+            Some(filename) => {
+                let file = UploadedFile {
+                    filename,
+                    content_type: content_type.unwrap_or_else(|| String::from("application/octet-stream")),
+                    data:         store(content, limits)?,
+                    size:         content.len(),
+                };
+
+                multipart.files.entry(name).or_default().push(file);
+            },
+            None           => fields.push((name, String::from_utf8_lossy(content).into_owned())),
+        }
+
+        position = content_end + next_part.len();
+    }
+
+    multipart.fields = crate::request::multimap(fields);
+
+    Ok(multipart)
+}
+
+/// Reads the name, filename and content type of a part from its headers.
+fn part_headers(head: &str) -> Result<(String, Option<String>, Option<String>), MultipartError> {
+    let mut disposition: Option<&str> = None;
+    let mut content_type: Option<String> = None;
+
+    for line in head.split("\r\n") {
+        let (name, value) = line.split_once(':').ok_or(MultipartError::Malformed("part has a header without a colon"))?;
+
+        if name.trim().eq_ignore_ascii_case("content-disposition") {
+            disposition = Some(value.trim());
+        } else if name.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    let disposition = disposition.ok_or(MultipartError::Malformed("part has no content-disposition"))?;
+
+    let name = disposition_param(disposition, "name").ok_or(MultipartError::Malformed("part has no name"))?;
+
+    // some browsers send the whole path of the file on the client
+    let filename = disposition_param(disposition, "filename")
+                       .map(|filename| filename.rsplit(['/', '\\']).next().unwrap_or("").to_string());
+
+    Ok((name, filename, content_type))
+}
+
+/// returns a parameter of a content-disposition like `form-data; name="photo"; filename="cat.png"`.
+fn disposition_param(disposition: &str, param: &str) -> Option<String> {
+    let mut rest = disposition;
+
+    while let Some(start) = rest.find(';') {
+        rest = rest[start + 1..].trim_start();
+
+        let (name, value) = rest.split_once('=')?;
+
+        // a quoted value can contain semicolons, so it is read up to its closing quote
+        let (value, after) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            },
+            None         => {
+                let end = value.find(';').unwrap_or(value.len());
+                (value[..end].trim(), &value[end..])
+            },
+        };
+
+        if name.trim().eq_ignore_ascii_case(param) {
+            return Some(value.to_string());
+        }
+
+        rest = after;
+    }
+
+    None
+}
+
+/// Keeps a file in memory, or writes it to a new temporary file when it is larger than `memory_limit`.
+fn store(content: &[u8], limits: &MultipartLimits) -> Result<Data, MultipartError> {
+    static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+    if content.len() <= limits.memory_limit {
+        return Ok(Data::Memory(content.to_vec()));
+    }
+
+    let dir = limits.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+
+    // the random part keeps others sharing the directory from guessing the name and creating it first
+    let mut random = [0u8; 8];
+    getrandom::getrandom(&mut random).map_err(|e| io::Error::other(e.to_string()))?;
+
+    let path = dir.join(format!("orangutan-upload-{}-{}-{:016x}",
+                                std::process::id(), UPLOADS.fetch_add(1, Ordering::Relaxed), u64::from_ne_bytes(random)));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    // the temporary directory is shared with other users, who shouldn't be able to read the uploads
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&path)?;
+
+    if let Err(e) = file.write_all(content) {
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+
+    Ok(Data::Disk(path))
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A body with the given parts, each a content-disposition and content, delimited by `boundary`.
+    fn body(boundary: &str, parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = String::new();
+
+        for (disposition, content) in parts {
+            body.push_str(&format!("--{}\r\nContent-Disposition: form-data; {}\r\n\r\n{}\r\n", boundary, disposition, content));
+        }
+
+        body.push_str(&format!("--{}--\r\n", boundary));
+        body.into_bytes()
+    }
+
+    #[test]
+    fn boundary_is_read_from_the_content_type() {
+        assert_eq!(boundary("multipart/form-data; boundary=abc").unwrap().unwrap(), "abc");
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; BOUNDARY=\"x-y z\"").unwrap().unwrap(), "x-y z");
+        assert!(boundary("application/x-www-form-urlencoded").is_none());
+
+        for content_type in ["multipart/form-data", "multipart/form-data; boundary=", "multipart/form-data; boundary=\"\""] {
+            assert!(matches!(boundary(content_type), Some(Err(MultipartError::MissingBoundary))), "{:?}", content_type);
+        }
+
+        let long = format!("multipart/form-data; boundary={}", "a".repeat(71));
+        assert!(matches!(boundary(&long), Some(Err(MultipartError::MissingBoundary))));
+    }
+
+    #[test]
+    fn preamble_and_epilogue_are_ignored() {
+        let mut content = b"this is the preamble\r\n".to_vec();
+        content.extend(body("xyz", &[(r#"name="a""#, "1"), (r#"name="b""#, "two\r\nlines")]));
+        content.extend_from_slice(b"and the epilogue");
+
+        let multipart = parse(&content, "xyz", &MultipartLimits::default()).unwrap();
+
+        assert_eq!(multipart.fields.len(), 2);
+        assert_eq!(multipart.fields["a"].first(), "1");
+        assert_eq!(multipart.fields["b"].first(), "two\r\nlines");
+        assert!(multipart.files.is_empty());
+    }
+
+    #[test]
+    fn filenames_keep_quoted_semicolons_and_lose_their_directories() {
+        let content = body("b", &[
+            (r#"name="doc"; filename="a;b.txt""#, "semicolon"),
+            (r#"name="photo"; filename="C:\Users\me\cat.png""#, "windows"),
+            (r#"filename="/home/me/dog.png"; name="photo""#, "unix"),
+        ]);
+
+        let multipart = parse(&content, "b", &MultipartLimits::default()).unwrap();
+        let photos: Vec<&str> = multipart.files["photo"].iter().map(|file| file.filename.as_str()).collect();
+
+        assert_eq!(multipart.file("doc").unwrap().filename, "a;b.txt");
+        assert_eq!(multipart.file("doc").unwrap().bytes().unwrap(), b"semicolon");
+        assert_eq!(multipart.file("doc").unwrap().content_type, "application/octet-stream");
+        assert_eq!(photos, ["cat.png", "dog.png"]);
+    }
+
+    #[test]
+    fn missing_closing_boundary_is_malformed() {
+        let unclosed = b"--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end";
+        let no_boundary = b"just some text";
+
+        assert!(matches!(parse(unclosed, "b", &MultipartLimits::default()), Err(MultipartError::Malformed(_))));
+        assert!(matches!(parse(no_boundary, "b", &MultipartLimits::default()), Err(MultipartError::Malformed(_))));
+    }
+
+    #[test]
+    fn parts_over_the_limits_are_rejected() {
+        let limits = MultipartLimits { max_parts: 2, max_part_size: 4, ..MultipartLimits::default() };
+
+        let two = body("b", &[(r#"name="a""#, "1234"), (r#"name="b""#, "")]);
+        let three = body("b", &[(r#"name="a""#, "1"), (r#"name="b""#, "2"), (r#"name="c""#, "3")]);
+        let large = body("b", &[(r#"name="a""#, "1"), (r#"name="big"; filename="big.bin""#, "12345")]);
+
+        assert!(parse(&two, "b", &limits).is_ok());
+        assert!(matches!(parse(&three, "b", &limits), Err(MultipartError::TooManyParts)));
+        assert!(matches!(parse(&large, "b", &limits), Err(MultipartError::PartTooLarge(name)) if name == "big"));
+    }
+
+    #[test]
+    fn large_files_are_spilled_to_disk_and_removed_on_drop() {
+        let dir = std::env::temp_dir().join(format!("orangutan-multipart-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let limits = MultipartLimits { memory_limit: 4, temp_dir: Some(dir.clone()), ..MultipartLimits::default() };
+        let content = body("b", &[(r#"name="small"; filename="s.txt""#, "1234"), (r#"name="large"; filename="l.txt""#, "12345")]);
+
+        let multipart = parse(&content, "b", &limits).unwrap();
+        let small = multipart.file("small").unwrap();
+        let large = multipart.file("large").unwrap();
+        let path = large.path().unwrap().to_path_buf();
+
+        assert!(small.path().is_none());
+        assert_eq!(path.parent(), Some(dir.as_path()));
+        assert!(path.file_name().unwrap().to_str().unwrap().starts_with(&format!("orangutan-upload-{}-", std::process::id())));
+        assert_eq!(large.len(), 5);
+        assert_eq!(large.bytes().unwrap(), b"12345");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        drop(multipart);
+
+        assert!(!path.exists());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
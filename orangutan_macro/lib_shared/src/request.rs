@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 use serde_json::Value;
 
+pub use crate::multipart::{Multipart, MultipartError, MultipartLimits, UploadedFile};
+use crate::session::Session;
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum ContentType {
     ApplicationJson,
@@ -14,25 +17,87 @@ pub enum QueryArg {
     Multiple(Vec<String>),    
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+impl QueryArg {
+    /// returns the value, or the first one when the argument was given more than once.
+    pub fn first(&self) -> &str {
+        match self {
+            QueryArg::Single(value)    => value,
+            QueryArg::Multiple(values) => &values[0],
+        }
+    }
+
+    /// returns every value, in the order they were given.
+    pub fn values(&self) -> Vec<&str> {
+        match self {
+            QueryArg::Single(value)    => vec![value.as_str()],
+            QueryArg::Multiple(values) => values.iter().map(String::as_str).collect(),
+        }
+    }
+
+    fn push(&mut self, value: String) {
+        match self {
+            QueryArg::Single(first)    => *self = QueryArg::Multiple(vec![std::mem::take(first), value]),
+            QueryArg::Multiple(values) => values.push(value),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Method {
     GET,    
     POST,
     PUT,
     DELETE,
     OPTIONS,       
+    PATCH,
+    /// Answered by the GET handler of the route when there is no HEAD handler, with the body left out.
+    HEAD,
+    /// Any other method, for example the WebDAV ones like PROPFIND or MKCOL. Methods are case-sensitive.
+    Extension(String),
     NONE, 
 }
 
+impl Method {
+    /// returns the name of the method as it is sent in a request.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::GET          => "GET",
+            Method::POST         => "POST",
+            Method::PUT          => "PUT",
+            Method::DELETE       => "DELETE",
+            Method::OPTIONS      => "OPTIONS",
+            Method::PATCH        => "PATCH",
+            Method::HEAD         => "HEAD",
+            Method::Extension(m) => m,
+            Method::NONE         => "",
+        }
+    }
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub enum Version {
+    HTTP10,
+    HTTP11,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Request {
     pub method: Method,
+    pub version: Version,
     pub uri: String,
     pub path: String,
     pub query: Option<String>,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
+    pub trailers: HashMap<String, String>,
     pub params: HashMap<String, String>,
+    pub session: Session,
 }
 
 impl Request {
@@ -42,20 +107,26 @@ impl Request {
     ///     Some important fields:
     /// 
     ///  method: Represents the http method 
+    ///  version: Represents the http version the client spoke. Decides if the connection is kept alive by default
     ///  path: Represents the path that the Request uses
     ///  headers: Represents the headers of a Request
     ///  body: The body/data of the Request. Containts the "data"
+    ///  trailers: The fields sent after a chunked body. Kept apart from the headers, since a proxy in front of the server only checks the headers
     ///  params: The params of the Request. Example: if the path is "/<int:test>", then the params are the int variable and the naem of the variable is test.    
+    ///  session: The values kept for the client between requests, see Session
 
     pub fn new() -> Request {
         Request {
             method: Method::NONE,
+            version: Version::HTTP11,
             uri: String::new(),
             path: String::new(),
             query: None,
             headers: HashMap::new(),
             body: Vec::new(),    
+            trailers: HashMap::new(),
             params: HashMap::new(),
+            session: Session::default(),
         }
     }        
 
@@ -72,20 +143,228 @@ impl Request {
     /// 
     ///     #[route(path="/<int:name_defined_here>", method="[POST, GET]")]
     ///     fn double_handler(req: &Request) -> Response {
-    ///         let pulled_variable: i32 = req.get_var("not_the_same_name"); // This panics because:
+    ///         let pulled_variable: i32 = req.get_var("not_the_same_name"); // This answers 404 because:
     ///         // the path defined in the route_macro is NOT the same as the one given to the get_var() function
     ///
     ///         let mut res = Response::new();              
     ///
     ///         res
     ///     }    
+    /// 
+    /// A variable that doesn't exist stops the handler and is answered with 404, a value that doesn't fit in `T`
+    /// (like 300 for a u8) with 400. Use `try_var` to handle those yourself.
 
     pub fn get_var<T: FromUri>(&self, name: &str) -> T {
-        if !self.params.contains_key(name) {
-            panic!("Invalid route parameter {:?}", name);
+        // resume_unwind skips the panic hook, so no panic message is printed for what is the client's mistake
+        self.try_var(name).unwrap_or_else(|e| std::panic::resume_unwind(Box::new(e)))
+    }
+
+    /// Pulls the variable from the route like `get_var`, returning an error instead of stopping the handler.
+    /// 
+    /// ```ignore
+    /// #[route(path="/items/<uint:count>", method="[GET]")]
+    /// fn items_handler(req: &Request) -> Response {
+    ///     let mut res = Response::new();
+    ///
+    ///     match req.try_var::<u8>("count") {
+    ///         Ok(count) => res.insert(format!("{} items", count)),
+    ///         Err(_)    => res.insert("that's too many items"),
+    ///     }
+    ///
+    ///     res
+    /// }
+    /// ```
+
+    pub fn try_var<T: FromUri>(&self, name: &str) -> std::result::Result<T, ParamError> {
+        let value = self.params.get(name).ok_or_else(|| ParamError::Missing(name.to_string()))?;
+
+        T::from_uri(value).map_err(|reason| ParamError::Invalid {
+            name:   name.to_string(),
+            value:  value.clone(),
+            reason,
+        })
+    }
+
+    /// returns the arguments of the query string, percent-decoded and with `+` read as a space.
+    /// An argument given more than once, like `tag` in `?tag=a&tag=b`, is a `QueryArg::Multiple`.
+    /// 
+    /// ```ignore
+    /// #[route(path="/posts", method="[GET]")]
+    /// fn posts_handler(req: &Request) -> Response {
+    ///     let args = req.args();
+    ///
+    ///     let tags: Vec<&str> = args.get("tag").map_or(Vec::new(), |tag| tag.values());
+    ///
+    ///     let mut res = Response::new();
+    ///
+    ///     res.insert(format!("posts tagged {}", tags.join(", ")));
+    ///
+    ///     res
+    /// }
+    /// ```
+
+    pub fn args(&self) -> HashMap<String, QueryArg> {
+        multimap(parse_pairs(self.query.as_deref().unwrap_or("")))
+    }
+
+    /// returns the query argument as `T`, or None if the query doesn't have it or it can't be read as `T`, like `?page=two`.
+    /// The first value is used if there are many.
+    /// 
+    /// ```
+    /// # let mut req = lib_shared::request::Request::new();
+    /// # req.query = Some(String::from("page=2"));
+    /// let page: u32 = req.arg("page").unwrap_or(1); // ?page=2
+    /// # assert_eq!(page, 2);
+    /// ```
+    /// 
+    /// `try_arg` tells the two apart.
+
+    pub fn arg<T: FromUri>(&self, name: &str) -> Option<T> {
+        self.try_arg(name).ok()
+    }
+
+    /// returns every value of the query argument as `T`, like `["a", "b"]` for `?tag=a&tag=b`. 
+    /// A value that can't be read as `T` stops the handler and is answered with 400. Use `try_arg_values` to handle that yourself.
+
+    pub fn arg_values<T: FromUri>(&self, name: &str) -> Vec<T> {
+        self.try_arg_values(name).unwrap_or_else(|e| std::panic::resume_unwind(Box::new(e)))
+    }
+
+    /// returns every value of the query argument as `T` like `arg_values`, with the error of the first value
+    /// that can't be read instead of stopping the handler. An argument that isn't there gives an empty Vec.
+
+    pub fn try_arg_values<T: FromUri>(&self, name: &str) -> std::result::Result<Vec<T>, ParamError> {
+        read_all(&self.args(), name)
+    }
+
+    /// returns the query argument as `T` like `arg`, with an error that says whether it is missing or invalid instead of None.
+
+    pub fn try_arg<T: FromUri>(&self, name: &str) -> std::result::Result<T, ParamError> {
+        read_first(&self.args(), name)
+    }
+
+    /// returns the fields of an `application/x-www-form-urlencoded` body, decoded like `args`.
+    /// 
+    /// Returns None when the request says its body is something else, so a JSON or multipart body isn't mistaken for a form.
+    /// 
+    /// ```ignore
+    /// #[route(path="/login", method="[POST]")]
+    /// fn login_handler(req: &Request) -> Response {
+    ///     let mut res = Response::new();
+    ///
+    ///     match req.form() {
+    ///         Some(form) => res.insert(format!("hello {}", form.get("user").map_or("", |user| user.first()))),
+    ///         None       => res.abort(req, 400),
+    ///     }
+    ///
+    ///     res
+    /// }
+    /// ```
+
+    pub fn form(&self) -> Option<HashMap<String, QueryArg>> {
+        if self.media_type().as_deref() != Some("application/x-www-form-urlencoded") {
+            return None;
         }
 
-        FromUri::from_uri(&self.params[name])
+        Some(multimap(parse_pairs(&String::from_utf8_lossy(&self.body))))
+    }
+
+    /// returns the form field as `T`, or None if the form doesn't have it, the body isn't a form or it can't be read as `T`.
+    /// 
+    /// ```
+    /// # let mut req = lib_shared::request::Request::new();
+    /// # req.headers.insert(String::from("content-type"), String::from("application/x-www-form-urlencoded"));
+    /// # req.body = b"age=30".to_vec();
+    /// let age: u8 = req.field("age").unwrap_or(0);
+    /// # assert_eq!(age, 30);
+    /// ```
+
+    pub fn field<T: FromUri>(&self, name: &str) -> Option<T> {
+        self.try_field(name).ok()
+    }
+
+    /// returns every value of the form field as `T`, like the checked boxes of a group of checkboxes.
+    /// A value that can't be read as `T` stops the handler and is answered with 400, like `arg_values`.
+
+    pub fn field_values<T: FromUri>(&self, name: &str) -> Vec<T> {
+        self.try_field_values(name).unwrap_or_else(|e| std::panic::resume_unwind(Box::new(e)))
+    }
+
+    /// returns every value of the form field as `T` like `field_values`, with the error of the first value
+    /// that can't be read instead of stopping the handler.
+
+    pub fn try_field_values<T: FromUri>(&self, name: &str) -> std::result::Result<Vec<T>, ParamError> {
+        read_all(&self.form().unwrap_or_default(), name)
+    }
+
+    /// returns the form field as `T` like `field`, with an error that says whether it is missing or invalid instead of None.
+
+    pub fn try_field<T: FromUri>(&self, name: &str) -> std::result::Result<T, ParamError> {
+        read_first(&self.form().unwrap_or_default(), name)
+    }
+
+    /// returns the fields and files of a multipart/form-data body, the kind an HTML form with a file input posts.
+    /// 
+    /// ```ignore
+    /// #[route(path="/avatar", method="[POST]")]
+    /// fn avatar_handler(req: &Request) -> Response {
+    ///     let mut res = Response::new();
+    ///
+    ///     let upload = match req.multipart() {
+    ///         Ok(upload) => upload,
+    ///         Err(e)     => { res.abort(req, e.status()); return res; },
+    ///     };
+    ///
+    ///     if let Some(file) = upload.file("avatar") {
+    ///         file.save(format!("avatars/{}", file.filename)).unwrap();
+    ///     }
+    ///
+    ///     res
+    /// }
+    /// ```
+    /// 
+    /// Uses the default MultipartLimits: at most 128 parts of 16 MiB each, and files over 256 KiB are written to a temporary file.
+    /// The body is already in memory when this is called, up to the `max_body_size` of the server.
+
+    pub fn multipart(&self) -> std::result::Result<Multipart, MultipartError> {
+        self.multipart_with(&MultipartLimits::default())
+    }
+
+    /// returns the fields and files of a multipart/form-data body like `multipart`, within `limits`.
+
+    pub fn multipart_with(&self, limits: &MultipartLimits) -> std::result::Result<Multipart, MultipartError> {
+        let ctype = self.headers.get("content-type").ok_or(MultipartError::NotMultipart)?;
+        let boundary = crate::multipart::boundary(ctype).ok_or(MultipartError::NotMultipart)??;
+
+        crate::multipart::parse(&self.body, &boundary, limits)
+    }
+
+    /// returns the cookies the client sent, by name, with their values decoded.
+    /// 
+    /// ```
+    /// # let mut req = lib_shared::request::Request::new();
+    /// # req.headers.insert(String::from("cookie"), String::from("theme=dark"));
+    /// let theme = req.cookies().get("theme").cloned().unwrap_or(String::from("light"));
+    /// # assert_eq!(theme, "dark");
+    /// ```
+
+    pub fn cookies(&self) -> HashMap<String, String> {
+        self.headers.get("cookie").map_or(HashMap::new(), |header| crate::cookie::parse(header))
+    }
+
+    /// returns the value of a cookie the client sent.
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
+    /// returns the media type of the body from the content-type header, in lowercase and without parameters like charset.
+
+    pub fn media_type(&self) -> Option<String> {
+        let ctype = self.headers.get("content-type")?;
+        let media_type = ctype.split(';').next().unwrap_or("").trim();
+
+        Some(media_type.to_ascii_lowercase())
     }
 
     /// returns Request in json.
@@ -107,53 +386,175 @@ impl Request {
         }            
     }
 
+    /// returns true if the client wants the connection to stay open after this Request.
+    /// 
+    /// HTTP/1.1 connections are persistent unless the client sends "Connection: close", 
+    /// HTTP/1.0 connections are closed unless the client sends "Connection: keep-alive".
+
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get("connection").map(|value| value.to_lowercase());
+        let has_token = |token: &str| connection.as_ref()
+                                                .map_or(false, |value| value.split(',').any(|t| t.trim() == token));
+
+        match self.version {
+            Version::HTTP11 => !has_token("close"),
+            Version::HTTP10 => has_token("keep-alive"),
+        }
+    }
+
     /// returns the Request's body as string
 
     pub fn get_string(&self) -> String {
         return String::from_utf8(self.body.clone()).expect("Bytes should be valid utf8");
     }    
 
-    pub fn parse(&mut self, rqstr: &str) -> std::result::Result<(), RequestError> {
-        let mut lines = rqstr.split("\r\n");
-                
-        if let Some(request_line) = lines.next() {
-            let parts: Vec<&str> = request_line.splitn(3, ' ').collect();
-            if parts.len() != 3 {
-                return Err(RequestError::InvalidRequestLine);
-            }
-            self.method = match_method(parts[0]);
-            self.uri = parts[1].to_string();
-            self.parse_uri(parts[1]);
-        } else {
+    /// Fills the Request from raw bytes: the request line, the headers and, after the empty line, the body.
+    /// 
+    /// The request line and headers are read as ASCII (other bytes are kept as latin-1 characters),
+    /// the body is stored exactly as it was sent, so binary uploads reach the handler untouched.
+
+    pub fn parse(&mut self, raw: &[u8]) -> std::result::Result<(), RequestError> {
+        let (head, body) = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => (&raw[..end], &raw[end + 4..]),
+            None      => (raw, &raw[raw.len()..]),
+        };
+
+        let head: String = head.iter().map(|&b| b as char).collect();
+        let mut lines = head.split("\r\n");
+
+        let request_line = lines.next().unwrap_or("");
+        let parts: Vec<&str> = request_line.split(' ').collect();
+
+        if parts.len() != 3 || !is_token(parts[0]) || !is_target(parts[1]) {
             return Err(RequestError::InvalidRequestLine);
         }
-        
-        for line in lines.by_ref() {
-            if line.is_empty() {
-                break; 
-            }
 
-            if let Some((name, value)) = line.split_once(": ") {
-                self.headers.insert(name.to_lowercase(), value.to_string());
+        if parts[1].len() > MAX_URI_LENGTH {
+            return Err(RequestError::UriTooLong);
+        }
+
+        self.method = match_method(parts[0]);
+        self.version = parse_version(parts[2])?;
+        self.uri = parts[1].to_string();
+        self.parse_uri(parts[1]);
+
+        for line in lines {
+            // the header name has to follow the start of the line and end right at the colon, anything else is ambiguous
+            let (name, value) = line.split_once(':').ok_or(RequestError::InvalidHeader)?;
+
+            if !is_token(name) || value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+                return Err(RequestError::InvalidHeader);
             }
+
+            self.headers.insert(name.to_lowercase(), value.trim().to_string());
         }
-        
-        self.body = lines.collect::<Vec<&str>>().join("\r\n").into_bytes();
+
+        self.body = body.to_vec();
 
         Ok(())
     }
 
     fn parse_uri(&mut self, uri: &str) {
+        // an absolute target ("http://host/path") is routed by its path
+        let uri = match uri.split_once("://") {
+            Some((_, rest)) => &rest[rest.find(['/', '?']).unwrap_or(rest.len())..],
+            None            => uri,
+        };
+
         if let Some((path, query)) = uri.split_once('?') {
-            self.path = path.to_string();
+            self.path = if path.is_empty() { String::from("/") } else { path.to_string() };
             self.query = Some(query.to_string());
         } else {
-            self.path = uri.to_string();
+            self.path = if uri.is_empty() { String::from("/") } else { uri.to_string() };
             self.query = None;
         }
     }
 }
 
+/// Collects names and values into a QueryArg for every name, keeping the order of repeated values.
+pub(crate) fn multimap(pairs: Vec<(String, String)>) -> HashMap<String, QueryArg> {
+    let mut map: HashMap<String, QueryArg> = HashMap::new();
+
+    for (name, value) in pairs {
+        match map.get_mut(&name) {
+            Some(arg) => arg.push(value),
+            None      => { map.insert(name, QueryArg::Single(value)); },
+        }
+    }
+
+    map
+}
+
+fn read_first<T: FromUri>(map: &HashMap<String, QueryArg>, name: &str) -> std::result::Result<T, ParamError> {
+    let value = map.get(name).map(QueryArg::first).ok_or_else(|| ParamError::Missing(name.to_string()))?;
+
+    T::from_uri(value).map_err(|reason| ParamError::Invalid {
+        name:  name.to_string(),
+        value: value.to_string(),
+        reason,
+    })
+}
+
+/// Reads every value of `name`, or the ParamError of the first one that doesn't fit.
+fn read_all<T: FromUri>(map: &HashMap<String, QueryArg>, name: &str) -> std::result::Result<Vec<T>, ParamError> {
+    let values = map.get(name).map_or(Vec::new(), QueryArg::values);
+
+    values.into_iter().map(|value| {
+        T::from_uri(value).map_err(|reason| ParamError::Invalid {
+            name:  name.to_string(),
+            value: value.to_string(),
+            reason,
+        })
+    }).collect()
+}
+
+/// Splits `a=1&b=2` into decoded names and values. A name without `=` has an empty value.
+pub(crate) fn parse_pairs(input: &str) -> Vec<(String, String)> {
+    input.split('&')
+         .filter(|pair| !pair.is_empty())
+         .map(|pair| {
+             let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+             (decode(name), decode(value))
+         })
+         .collect()
+}
+
+/// Decodes `%XX` escapes and `+` as used in query strings and urlencoded forms.
+pub(crate) fn decode(input: &str) -> String {
+    percent_decode(input, true)
+}
+
+/// Decodes `%XX` escapes, and `+` as a space if `plus_as_space`.
+/// Broken escapes are kept as they are and bytes that aren't UTF-8 become U+FFFD.
+pub(crate) fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' if bytes.len() > i + 2 && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                decoded.push(hex_value(bytes[i + 1]) << 4 | hex_value(bytes[i + 2]));
+                i += 2;
+            },
+            byte => decoded.push(byte),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _           => digit - b'A' + 10,
+    }
+}
+
 pub fn match_method(method: &str) -> Method {
     match method {
         "GET" => Method::GET, 
@@ -161,15 +562,76 @@ pub fn match_method(method: &str) -> Method {
         "PUT" => Method::PUT,
         "DELETE" => Method::DELETE,
         "OPTIONS" => Method::OPTIONS,        
+        "PATCH" => Method::PATCH,
+        "HEAD" => Method::HEAD,
+        _ if is_token(method) => Method::Extension(method.to_string()),
         _ => { Method::NONE },
     }
 }
 
+pub fn match_version(version: &str) -> Version {
+    match version {
+        "HTTP/1.0" => Version::HTTP10,
+        _ => Version::HTTP11,
+    }
+}
+
+/// The longest request target the server accepts, in bytes.
+pub const MAX_URI_LENGTH: usize = 4096;
+
+/// Reads the version at the end of the request line. Other HTTP versions are well-formed but not spoken by the server.
+fn parse_version(version: &str) -> std::result::Result<Version, RequestError> {
+    match version {
+        "HTTP/1.0" => Ok(Version::HTTP10),
+        "HTTP/1.1" => Ok(Version::HTTP11),
+        _ => {
+            let number = version.strip_prefix("HTTP/").ok_or(RequestError::InvalidRequestLine)?;
+            let digits: Vec<&str> = number.split('.').collect();
+
+            if digits.len() == 2 && digits.iter().all(|d| d.len() == 1 && d.chars().all(|c| c.is_ascii_digit())) {
+                Err(RequestError::UnsupportedVersion)
+            } else {
+                Err(RequestError::InvalidRequestLine)
+            }
+        }
+    }
+}
+
+/// true for the characters allowed in methods and header names.
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+/// true for request targets the server can route: a path, an absolute URL or `*` for OPTIONS.
+fn is_target(s: &str) -> bool {
+    let absolute = s.starts_with("http://") || s.starts_with("https://");
+
+    (s.starts_with('/') || s == "*" || absolute) && s.chars().all(|c| c.is_ascii_graphic())
+}
+
 #[derive(Debug)]
 pub enum RequestError {
     JsonStrError(serde_json::Error),
     StrCopyError(std::string::FromUtf8Error),
+    /// The request line isn't a method, a target and a version separated by single spaces.
     InvalidRequestLine,
+    /// A header line has no colon, or its name or value contains characters that aren't allowed.
+    InvalidHeader,
+    /// The request speaks a version of HTTP other than 1.0 or 1.1.
+    UnsupportedVersion,
+    /// The request target is longer than `MAX_URI_LENGTH`.
+    UriTooLong,
+}
+
+impl RequestError {
+    /// The status the client is answered with.
+    pub fn status(&self) -> u16 {
+        match self {
+            RequestError::UnsupportedVersion => 505,
+            RequestError::UriTooLong         => 414,
+            _                                => 400,
+        }
+    }
 }
 
 impl std::str::FromStr for Request {
@@ -177,35 +639,160 @@ impl std::str::FromStr for Request {
     
     fn from_str(rqstr: &str) -> std::result::Result<Request, RequestError> {
         let mut req = Request::new();
-        req.parse(rqstr).unwrap();
+        req.parse(rqstr.as_bytes())?;
         Ok(req)
     }
 }
 
-pub trait FromUri {
-    fn from_uri(data: &str) -> Self;
+impl TryFrom<&[u8]> for Request {
+    type Error = RequestError;
+
+    fn try_from(raw: &[u8]) -> std::result::Result<Request, RequestError> {
+        let mut req = Request::new();
+        req.parse(raw)?;
+        Ok(req)
+    }
+}
+
+/// Why a route variable or a query argument couldn't be read.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParamError {
+    /// There is no variable or argument with the name.
+    Missing(String),
+    /// The value doesn't fit the type it was asked as.
+    Invalid { name: String, value: String, reason: String },
+}
+
+impl ParamError {
+    /// The status the client is answered with when `get_var`, `arg` or `field` stop the handler.
+    pub fn status(&self) -> u16 {
+        match self {
+            ParamError::Missing(_)     => 404,
+            ParamError::Invalid { .. } => 400,
+        }
+    }
+}
+
+impl std::fmt::Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamError::Missing(name)                  => write!(f, "{:?} is missing", name),
+            ParamError::Invalid { name, value, reason } => write!(f, "{:?} is {:?}: {}", name, value, reason),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// Reads a route variable as a type. The error tells why the value doesn't fit.
+pub trait FromUri: Sized {
+    fn from_uri(data: &str) -> std::result::Result<Self, String>;
 }
 
 impl FromUri for String {
-    fn from_uri(data: &str) -> String {
-        String::from(data)
+    fn from_uri(data: &str) -> std::result::Result<String, String> {
+        Ok(String::from(data))
     }
 }
 
-impl FromUri for i32 {
-    fn from_uri(data: &str) -> i32 {
-        data.parse::<i32>().expect("matched integer can't be parsed")
+macro_rules! from_uri_parse {
+    ($($t:ty),*) => {
+        $(
+            impl FromUri for $t {
+                fn from_uri(data: &str) -> std::result::Result<$t, String> {
+                    data.parse::<$t>().map_err(|e| format!("not a valid {}: {}", stringify!($t), e))
+                }
+            }
+        )*
+    };
+}
+
+from_uri_parse!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64, char);
+
+impl FromUri for bool {
+    fn from_uri(data: &str) -> std::result::Result<bool, String> {
+        match data {
+            "true" | "1"  => Ok(true),
+            "false" | "0" => Ok(false),
+            _             => Err(String::from("not true, false, 1 or 0")),
+        }
     }
 }
 
-impl FromUri for u32 {
-    fn from_uri(data: &str) -> u32 {
-        data.parse::<u32>().expect("matched integer can't be parsed")
+impl FromUri for chrono::NaiveDate {
+    fn from_uri(data: &str) -> std::result::Result<chrono::NaiveDate, String> {
+        chrono::NaiveDate::parse_from_str(data, "%Y-%m-%d").map_err(|e| format!("not a date like 2024-02-29: {}", e))
     }
 }
 
-impl FromUri for f32 {
-    fn from_uri(data: &str) -> f32 {
-        data.parse::<f32>().expect("matched float can't be parsed")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The status a request is rejected with, or 200 if it parses.
+    fn status(raw: &str) -> u16 {
+        Request::try_from(raw.as_bytes()).map_or_else(|e| e.status(), |_| 200)
+    }
+
+    #[test]
+    fn well_formed_request_is_read() {
+        let request = Request::try_from(&b"POST /items?page=2 HTTP/1.0\r\nHost: x\r\nX-Tab:\tvalue \r\n\r\n\x00\xffbody"[..]).unwrap();
+
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.version, Version::HTTP10);
+        assert_eq!(request.path, "/items");
+        assert_eq!(request.query.as_deref(), Some("page=2"));
+        assert_eq!(request.headers["host"], "x");
+        assert_eq!(request.headers["x-tab"], "value");
+        assert_eq!(request.body, b"\x00\xffbody");
+    }
+
+    #[test]
+    fn malformed_request_line_is_400() {
+        for line in ["", "GET /", "GET / HTTP/1.1 extra", "GET  / HTTP/1.1", "GET /  HTTP/1.1", " GET / HTTP/1.1",
+                     "GET / HTTP/1.1 ", "G(ET / HTTP/1.1", "GET path HTTP/1.1", "GET /a\u{7f}b HTTP/1.1", "GET / HTTPS/1.1"] {
+            assert_eq!(status(&format!("{}\r\n\r\n", line)), 400, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn malformed_header_is_400() {
+        for header in ["No-Colon", "Space : before", " Leading: space", "Bad\u{1}: name", "Control: a\u{0}b", "Escape: a\u{1b}b", ": empty"] {
+            assert_eq!(status(&format!("GET / HTTP/1.1\r\n{}\r\n\r\n", header)), 400, "{:?}", header);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn other_http_versions_are_505_and_anything_else_400() {
+        for version in ["HTTP/2.0", "HTTP/0.9", "HTTP/3.0"] {
+            assert_eq!(status(&format!("GET / {}\r\n\r\n", version)), 505, "{:?}", version);
+        }
+
+        for version in ["HTTP/x", "HTTP/1", "HTTP/1.1.1", "HTTP/10.0", "http/1.1", "HTTP/1.x"] {
+            assert_eq!(status(&format!("GET / {}\r\n\r\n", version)), 400, "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn long_target_is_414() {
+        let fits = format!("/{}", "a".repeat(MAX_URI_LENGTH - 1));
+        let too_long = format!("/{}", "a".repeat(MAX_URI_LENGTH));
+
+        assert_eq!(status(&format!("GET {} HTTP/1.1\r\n\r\n", fits)), 200);
+        assert_eq!(status(&format!("GET {} HTTP/1.1\r\n\r\n", too_long)), 414);
+    }
+
+    #[test]
+    fn asterisk_and_absolute_targets_are_routed_by_their_path() {
+        let asterisk = Request::try_from(&b"OPTIONS * HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let absolute = Request::try_from(&b"GET http://example.com/a/b?c=d HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let bare = Request::try_from(&b"GET https://example.com HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let query = Request::try_from(&b"GET http://example.com?x=1 HTTP/1.1\r\n\r\n"[..]).unwrap();
+
+        assert_eq!(asterisk.path, "*");
+        assert_eq!((absolute.path.as_str(), absolute.query.as_deref()), ("/a/b", Some("c=d")));
+        assert_eq!(absolute.uri, "http://example.com/a/b?c=d");
+        assert_eq!((bare.path.as_str(), bare.query.as_deref()), ("/", None));
+        assert_eq!((query.path.as_str(), query.query.as_deref()), ("/", Some("x=1")));
+    }
+}
@@ -1,12 +1,13 @@
-use std::collections::BTreeMap;
+use std::io::Read;
 use chrono::Utc;
 use serde_json::Value;
 
 use crate::Request;
 
 use crate::request::ContentType;
+use crate::cookie::Cookie;
 
-use crate::utils::{err_403, err_404, err_500};
+use crate::utils::{err_400, err_403, err_404, err_405, err_408, err_413, err_414, err_500, err_501, err_503, err_505};
 use crate::is_html;
 use crate::type_name;
 
@@ -17,8 +18,115 @@ pub struct Response {
     pub status:     u16,
     pub cmsg:       String,
     pub ctype:      String,
-    pub headers:    BTreeMap<String, String>,
+    pub headers:    Headers,
     pub payload:    Vec<u8>,
+    pub stream:     Option<Stream>,
+}
+
+/// The headers of a Response, in the order they were added. Names are compared without case, and a name can be
+/// there more than once, as Set-Cookie often is.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// returns the first value of the header.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    /// returns every value of the header, in the order they were added.
+    pub fn get_all(&self, name: &str) -> Vec<&String> {
+        self.entries.iter().filter(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value).collect()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets the header, replacing every value it had.
+    pub fn insert(&mut self, name: String, value: String) {
+        self.remove(&name);
+        self.entries.push((name, value));
+    }
+
+    /// Adds a value to the header, keeping the ones it had.
+    pub fn append(&mut self, name: String, value: String) {
+        self.entries.push((name, value));
+    }
+
+    /// Removes every value of the header.
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = (&'a String, &'a String);
+    type IntoIter = Box<dyn Iterator<Item = (&'a String, &'a String)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// A body that is sent piece by piece while it is being produced, instead of being built in memory first.
+/// 
+/// Streamed bodies are written with "Transfer-Encoding: chunked" unless the handler sets a Content-Length itself.
+/// The next piece is only read once the client has received the previous one, so a slow client slows down the stream instead of filling up memory.
+pub enum Stream {
+    Reader(Box<dyn Read + Send>),
+    Chunks(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+}
+
+impl Stream {
+    /// returns the next piece of the body, or None once the body has ended
+    pub fn next_chunk(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        match self {
+            Stream::Reader(reader) => {
+                let mut buf: Vec<u8> = vec![0; 16 * 1024];
+
+                loop {
+                    match reader.read(&mut buf) {
+                        Ok(0)       => return None,
+                        Ok(size)    => {
+                            buf.truncate(size);
+                            return Some(Ok(buf));
+                        },
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e)      => return Some(Err(e)),
+                    }
+                }
+            },
+            Stream::Chunks(chunks) => chunks.find(|chunk| !chunk.is_empty()).map(Ok),
+        }
+    }
+}
+
+impl std::fmt::Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stream::Reader(_) => f.write_str("Stream::Reader"),
+            Stream::Chunks(_) => f.write_str("Stream::Chunks"),
+        }
+    }
 }
 
 impl Response {
@@ -27,22 +135,61 @@ impl Response {
             status:     200,
             cmsg:       String::from("OK"),
             ctype:      String::from("text/plain"),
-            headers:    BTreeMap::new(),
+            headers:    Headers::new(),
             payload:    Vec::with_capacity(2048),
+            stream:     None,
         };
 
         let now = Utc::now().format("%a, %d %b %Y, %H:%M:%S %Z").to_string();
 
-        res.add_header("Connection", "close");
         res.add_header("Server", &format!("rustycomms/{}", VERSION));
         res.add_header("Date", &now);
 
         res
     }    
 
+    /// A Response whose body is read from `reader` while it is being sent.
+    /// 
+    /// ```ignore
+    /// #[route(path="/export", method="[GET]")]
+    /// fn export_handler(request: &Request) -> Response {
+    ///
+    ///     let file = std::fs::File::open("export.csv").unwrap();
+    ///
+    ///     Response::from_reader(file)
+    /// }
+    /// ```
+
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Response {
+        let mut res = Response::new();
+        res.stream = Some(Stream::Reader(Box::new(reader)));
+        res
+    }
+
+    /// A Response whose body is sent one chunk at a time as the iterator produces them.
+    /// 
+    /// A `std::sync::mpsc::Receiver<Vec<u8>>` works too, so the body can be produced on another thread.
+    /// 
+    /// ```ignore
+    /// #[route(path="/report", method="[GET]")]
+    /// fn report_handler(request: &Request) -> Response {
+    ///
+    ///     let rows = (0..100_000).map(|i| format!("row {}\n", i).into_bytes());
+    ///
+    ///     Response::from_chunks(rows)
+    /// }
+    /// ```
+
+    pub fn from_chunks<I>(chunks: I) -> Response 
+    where I: IntoIterator<Item = Vec<u8>>, I::IntoIter: Send + 'static {
+        let mut res = Response::new();
+        res.stream = Some(Stream::Chunks(Box::new(chunks.into_iter())));
+        res
+    }
+
     /// aborts the Request with an error.
     /// 
-    /// Possible error status: 403, 404, 500
+    /// Possible error status: 400, 403, 404, 405, 408, 413, 414, 500, 501, 503, 505
     ///
     ///     #[route(path="/test", method="[POST, GET]")]
     ///     fn hello_handler2(request: &Request) -> Response {
@@ -57,9 +204,17 @@ impl Response {
     
     pub fn abort(&mut self, req: &Request, status: u16) {
         let response = match status {
+            400 => err_400(req),
             403 => err_403(req),
             404 => err_404(req),
+            405 => err_405(req),
+            408 => err_408(req),
+            413 => err_413(req),
+            414 => err_414(req),
             500 => err_500(req),
+            501 => err_501(req),
+            503 => err_503(req),
+            505 => err_505(req),
             _ => err_404(req),
         };
 
@@ -68,10 +223,26 @@ impl Response {
         self.ctype = response.ctype;
         self.headers = response.headers;
         self.payload = response.payload;
+        self.stream = None;
     }
 
+    /// returns the Response as bytes ready to be sent. For a streamed Response only the head is returned,
+    /// the body follows as it is read from the Stream.
+
     pub fn to_bytes(&self) -> Vec<u8> {        
+        let mut response_bytes = self.head_bytes();
+
+        if self.stream.is_none() {
+            response_bytes.extend_from_slice(&self.payload);
+        }
+
+        response_bytes
+    }
+
+    /// returns only the status line and headers of the Response, as sent for a HEAD request. 
+    /// The Content-Length still tells the size of the body that was left out.
 
+    pub fn head_bytes(&self) -> Vec<u8> {
         let mut response_str = format!(
             "HTTP/1.1 {} {}\r\n",
             self.status,
@@ -82,17 +253,18 @@ impl Response {
             response_str.push_str(&format!("Content-Type: {}\r\n", self.ctype));
         }
 
+        // persistent connections need the length to know where the next response starts
+        if !self.headers.contains_key("Content-Length") && self.stream.is_none() {
+            response_str.push_str(&format!("Content-Length: {}\r\n", self.payload.len()));
+        }
+
         for (key, value) in &self.headers {
             response_str.push_str(&format!("{}: {}\r\n", key, value));
         }
         
         response_str.push_str("\r\n");
 
-        let mut response_bytes = response_str.into_bytes();
-
-        response_bytes.extend_from_slice(&self.payload);
-
-        response_bytes
+        response_str.into_bytes()
     }
 
     /// a way to add a header to a Response
@@ -103,6 +275,44 @@ impl Response {
         }
     }
 
+    /// adds a header even if the Response already has one with the same name, for headers that can be repeated
+
+    pub fn append_header(&mut self, key: &str, value: &str) {
+        self.headers.append(String::from(key), String::from(value));
+    }
+
+    /// asks the client to store a cookie
+    /// 
+    /// ```
+    /// # use lib_shared::cookie::Cookie;
+    /// # use std::time::Duration;
+    /// # let mut res = lib_shared::response::Response::new();
+    /// res.set_cookie(Cookie::new("theme", "dark").path("/").max_age(Duration::from_secs(30 * 24 * 3600)));
+    /// ```
+
+    pub fn set_cookie(&mut self, cookie: Cookie) {
+        self.append_header("Set-Cookie", &cookie.to_string());
+    }
+
+    /// asks the client to remove a cookie that was set with Path=/. 
+    /// A cookie set with another path or a domain is removed with `set_cookie(Cookie::new(name, "").path(..).expire())`.
+
+    pub fn delete_cookie(&mut self, name: &str) {
+        self.set_cookie(Cookie::new(name, "").path("/").expire());
+    }
+
+    /// returns true if the handler asked for the connection to be closed after this Response
+    /// 
+    /// ```
+    /// # let mut res = lib_shared::response::Response::new();
+    /// res.add_header("Connection", "close");
+    /// # assert!(res.closes_connection());
+    /// ```
+
+    pub fn closes_connection(&self) -> bool {
+        self.headers.get("Connection").map_or(false, |value| value.eq_ignore_ascii_case("close"))
+    }
+
     /// a way to add a status to a Response
 
     pub fn set_status(&mut self, status: u16) {
@@ -151,8 +361,9 @@ impl Response {
         self.status = 200;
         self.cmsg = String::from("OK");
         self.ctype = String::from("text/plain");
-        self.headers = BTreeMap::new();
+        self.headers = Headers::new();
         self.payload = Vec::with_capacity(2048);
+        self.stream = None;
     }
 
     /// A simple way to make a Response have some text/json/html in it.
@@ -276,4 +487,66 @@ impl ToOutput for i32 {
     fn to_output(&self) -> Vec<u8> { 
         self.to_owned().to_string().as_bytes().to_vec()   
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_names_are_compared_without_case() {
+        let mut headers = Headers::new();
+        headers.insert(String::from("X-Thing"), String::from("1"));
+        headers.append(String::from("x-thing"), String::from("2"));
+        headers.append(String::from("Other"), String::from("3"));
+
+        assert_eq!(headers.get("X-THING"), Some(&String::from("1")));
+        assert_eq!(headers.get_all("x-Thing"), [&String::from("1"), &String::from("2")]);
+        assert_eq!(headers.len(), 3);
+
+        headers.insert(String::from("X-THING"), String::from("4"));
+        assert_eq!(headers.get_all("x-thing"), [&String::from("4")]);
+
+        headers.remove("x-thing");
+        assert!(!headers.contains_key("X-Thing"));
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn add_header_keeps_the_first_value() {
+        let mut res = Response::new();
+        res.add_header("Cache-Control", "no-store");
+        res.add_header("cache-control", "max-age=60");
+
+        assert_eq!(res.headers.get_all("Cache-Control"), [&String::from("no-store")]);
+    }
+
+    #[test]
+    fn repeated_headers_are_each_sent_in_order() {
+        let mut res = Response::new();
+        res.set_cookie(Cookie::new("a", "1"));
+        res.append_header("Link", "</style.css>; rel=preload");
+        res.set_cookie(Cookie::new("b", "2").http_only(true));
+        res.delete_cookie("old");
+        res.insert("body");
+
+        let bytes = String::from_utf8(res.to_bytes()).unwrap();
+        let (head, body) = bytes.split_once("\r\n\r\n").unwrap();
+        let cookies: Vec<&str> = head.lines().filter_map(|line| line.strip_prefix("Set-Cookie: ")).collect();
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("\r\nContent-Length: 4\r\n"), "{}", head);
+        assert!(head.contains("\r\nLink: </style.css>; rel=preload\r\n"), "{}", head);
+        assert_eq!(cookies, ["a=1", "b=2; HttpOnly", "old=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"]);
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn given_content_length_is_not_repeated() {
+        let mut res = Response::new();
+        res.add_header("Content-Length", "10");
+
+        let bytes = String::from_utf8(res.head_bytes()).unwrap();
+
+        assert_eq!(bytes.matches("Content-Length").count(), 1, "{}", bytes);
+    }
+}
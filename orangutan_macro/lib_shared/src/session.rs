@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Values kept for a client from one request to the next, like who is logged in.
+///
+/// The server reads the session from the session cookie before the handler runs and sends it back
+/// after the handler returns, but only if the handler changed it. Values can be anything that can be written as JSON.
+///
+/// ```ignore
+/// #[route(path="/visits", method="[GET]")]
+/// fn visits_handler(request: &Request) -> Response {
+///     let visits: u32 = request.session.get("visits").unwrap_or(0) + 1;
+///
+///     request.session.insert("visits", visits);
+///
+///     let mut res = Response::new();
+///     res.insert(format!("visit number {}", visits));
+///     res
+/// }
+/// ```
+///
+/// The session is changed through `&Request`, so handlers keep their signature. It can be shared with other threads
+/// along with the Request, like the ones a handler starts to do part of its work.
+#[derive(Debug, Default)]
+pub struct Session {
+    values:     Mutex<Map<String, Value>>,
+    modified:   AtomicBool,
+    regenerate: AtomicBool,
+}
+
+impl Clone for Session {
+    fn clone(&self) -> Session {
+        Session {
+            values:     Mutex::new(self.values()),
+            modified:   AtomicBool::new(self.modified()),
+            regenerate: AtomicBool::new(self.regenerated()),
+        }
+    }
+}
+
+impl PartialEq for Session {
+    fn eq(&self, other: &Session) -> bool {
+        std::ptr::eq(self, other)
+            || (self.values() == other.values() && self.modified() == other.modified() && self.regenerated() == other.regenerated())
+    }
+}
+
+impl Eq for Session {}
+
+impl Session {
+    /// A session with the values that were stored for the client. It counts as unchanged.
+    pub fn from_values(values: Map<String, Value>) -> Session {
+        Session { values: Mutex::new(values), modified: AtomicBool::new(false), regenerate: AtomicBool::new(false) }
+    }
+
+    /// The values are only locked while a method runs, so a handler that panicked in one of them left them whole.
+    fn lock(&self) -> MutexGuard<'_, Map<String, Value>> {
+        self.values.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// returns the value as `T`, or None if the session doesn't have it or it isn't a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.lock().get(key).cloned()?;
+
+        serde_json::from_value(value).ok()
+    }
+
+    /// Sets a value, replacing the one the key had.
+    ///
+    /// Panics if the value can't be written as JSON, like a map with keys that aren't strings.
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) {
+        let value = serde_json::to_value(value).expect("session values must be representable as JSON");
+
+        self.lock().insert(String::from(key), value);
+        self.modified.store(true, Ordering::Relaxed);
+    }
+
+    /// Removes a value. returns true if the session had it.
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.lock().remove(key).is_some();
+
+        if removed {
+            self.modified.store(true, Ordering::Relaxed);
+        }
+
+        removed
+    }
+
+    /// Removes every value, like when logging out. An empty session removes the session cookie.
+    pub fn clear(&self) {
+        if !self.is_empty() {
+            self.lock().clear();
+            self.modified.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.lock().contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.lock().keys().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// returns true if the handler changed the session, so it has to be sent back.
+    pub fn modified(&self) -> bool {
+        self.modified.load(Ordering::Relaxed)
+    }
+
+    /// Marks the session as changed, for when a value was changed in a way `insert` doesn't see.
+    pub fn mark_modified(&self) {
+        self.modified.store(true, Ordering::Relaxed);
+    }
+
+    /// Moves the session to a new id when it is saved, keeping its values. Call it when the user logs in,
+    /// so an id someone else planted or saw before the login is worth nothing afterwards.
+    ///
+    /// Only sessions kept in a SessionStore have an id, a cookie session is just signed again.
+    pub fn regenerate(&self) {
+        self.regenerate.store(true, Ordering::Relaxed);
+        self.modified.store(true, Ordering::Relaxed);
+    }
+
+    /// returns true if `regenerate` was called.
+    pub fn regenerated(&self) -> bool {
+        self.regenerate.load(Ordering::Relaxed)
+    }
+
+    /// returns a copy of every value, to store the session.
+    pub fn values(&self) -> Map<String, Value> {
+        self.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Request;
+
+    #[test]
+    fn request_can_be_shared_with_other_threads() {
+        fn shared<T: Send + Sync>(_: &T) {}
+
+        let request = Request::new();
+        shared(&request);
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let request = &request;
+                scope.spawn(move || request.session.insert(&format!("worker{}", i), i));
+            }
+        });
+
+        assert_eq!(request.session.keys().len(), 4);
+        assert_eq!(request.session.get::<u32>("worker3"), Some(3));
+        assert!(request.session.modified());
+    }
+
+    #[test]
+    fn clone_keeps_values_and_flags() {
+        let session = Session::default();
+        session.insert("user", "someone");
+        session.regenerate();
+
+        let copy = session.clone();
+        copy.remove("user");
+
+        assert!(copy.regenerated() && copy.modified() && copy.is_empty());
+        assert_eq!(session.get::<String>("user").as_deref(), Some("someone"));
+        assert_ne!(session, copy);
+    }
+}
@@ -18,6 +18,11 @@ pub fn err_body(message: &str, path: &str) -> String {
              </head><body><h3>Your request failed</h3><p>{}: {}</p></body></html>", message, path)
 }
 
+/// Default handler function for HTTP 400 errors.
+pub fn err_400(req: &Request) -> Response {
+    make_response(err_body("bad request", &req.path), ContentType::TextHtml, 400)
+}
+
 /// Default handler function for HTTP 403 errors.
 pub fn err_403(req: &Request) -> Response {
     make_response(err_body("forbidden", &req.path), ContentType::TextHtml, 403)
@@ -33,11 +38,36 @@ pub fn err_404(req: &Request) -> Response {
     make_response(err_body("not found", &req.path), ContentType::TextHtml, 404) // tee tost kunno
 }
 
+/// Default handler function for HTTP 501 errors.
+pub fn err_501(req: &Request) -> Response {
+    make_response(err_body("not implemented", &req.path), ContentType::TextHtml, 501)
+}
+
 /// Default handler function for HTTP 500 errors for XHR.
 pub fn err_404_json(message: &str) -> Response {
     make_response(format!("{{ message: 'not found: {}' }}", message), ContentType::ApplicationJson, 404)
 }
 
+/// Default handler function for HTTP 405 errors.
+pub fn err_405(req: &Request) -> Response {
+    make_response(err_body("method not allowed", &req.path), ContentType::TextHtml, 405)
+}
+
+/// Default handler function for HTTP 408 errors.
+pub fn err_408(req: &Request) -> Response {
+    make_response(err_body("request timeout", &req.path), ContentType::TextHtml, 408)
+}
+
+/// Default handler function for HTTP 413 errors.
+pub fn err_413(req: &Request) -> Response {
+    make_response(err_body("request entity too large", &req.path), ContentType::TextHtml, 413)
+}
+
+/// Default handler function for HTTP 414 errors.
+pub fn err_414(req: &Request) -> Response {
+    make_response(err_body("request-uri too long", &req.path), ContentType::TextHtml, 414)
+}
+
 /// Default handler function for HTTP 500 errors.
 pub fn err_500(req: &Request) -> Response {
     make_response(err_body("internal server error", &req.path), ContentType::TextHtml, 500)
@@ -47,3 +77,13 @@ pub fn err_500(req: &Request) -> Response {
 pub fn err_500_json(message: &str) -> Response {
     make_response(format!("{{ message: 'internal server error: {}' }}", message), ContentType::ApplicationJson, 500)
 }
+
+/// Default handler function for HTTP 503 errors.
+pub fn err_503(req: &Request) -> Response {
+    make_response(err_body("service unavailable", &req.path), ContentType::TextHtml, 503)
+}
+
+/// Default handler function for HTTP 505 errors.
+pub fn err_505(req: &Request) -> Response {
+    make_response(err_body("http version not supported", &req.path), ContentType::TextHtml, 505)
+}
@@ -1,6 +1,10 @@
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
-use syn::{parse_macro_input, ItemFn};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Expr, ExprLit, FnArg, ItemFn, Lit, MetaNameValue, Pat, Token, Type};
+
+use lib_shared::Placeholder;
 
 /// The route macro used to define the path and the method for a handler.
 /// 
@@ -24,49 +28,67 @@ use syn::{parse_macro_input, ItemFn};
 /// 
 /// Here the path is "/hello" and the hello_handler function is responsible for handling any requests sent to the path.
 /// 
+/// The methods are GET, POST, PUT, DELETE, PATCH, HEAD and OPTIONS, or any other method name like PROPFIND.
+/// HEAD requests are answered by the GET handler when there is no HEAD handler.
+/// 
+/// Instead of calling `request.get_var`, a handler can take the path variables as arguments, named like the placeholders:
+/// 
+///     #[route(path="/users/<uint:id>/posts/<slug:post>", method="[GET]")]
+///     fn show_post(id: u32, post: String, request: &Request) -> Response {
+///         ...
+///     }
+/// 
+/// The arguments can be in any order and the `&Request` can be left out. An argument that isn't a placeholder of the path,
+/// or whose type can't hold the values of the converter (like `bool` for `<uint:id>`), is a compile error.
+/// 
 
 #[proc_macro_attribute]
 pub fn route(attr: TokenStream, item: TokenStream) -> TokenStream {
     let handler = parse_macro_input!(item as ItemFn);
     let handler_name = &handler.sig.ident;
 
-    let args = attr.to_string();
+    let args = match Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse(attr) {
+        Ok(args) => args,
+        Err(e)   => return e.to_compile_error().into(),
+    };
 
     let mut path = String::new();
     let mut methods: Vec<String> = Vec::new();    
 
-    let parts: Vec<&str> = args.split(',').collect();
+    // following code reads the path and methods. Values are string literals, so a path can contain commas like <any(json,xml):format>
 
-    // following code reads the path and methods
+    for arg in args {
+        let value = match &arg.value {
+            Expr::Lit(ExprLit { lit: Lit::Str(value), .. }) => value.value(),
+            other => return syn::Error::new_spanned(other, "expected a string literal").to_compile_error().into(),
+        };
 
-    for part in parts {
-        let trimmed = part.trim();
-        if trimmed.starts_with("path") {
-            if let Some((_, value)) = trimmed.split_once('=') {
-                path = value.trim_matches(|c| c == '"' || c == ' ').to_string();                
-            }
-        } else if trimmed.starts_with("method") {
-            if let Some((_, value)) = trimmed.split_once('=') {
-                let method_str = value.trim_matches(|c| c == '[' || c == ']' || c == '"');
-                methods = method_str.split(',')
-                                    .map(|s| s.trim_matches(|c| c == '"' || c == ' ' || c == '[' || c == ']').to_string())
-                                    .collect();
-            }
+        if arg.path.is_ident("path") {
+            path = value;
+        } else if arg.path.is_ident("method") {
+            methods = value.trim_matches(|c| c == '[' || c == ']')
+                           .split(',')
+                           .map(|s| s.trim_matches(|c| c == '"' || c == ' ').to_string())
+                           .filter(|s| !s.is_empty())
+                           .collect();
         } else {
-            let method_str = trimmed.trim_matches(|c| c == '[' || c == ']' || c == '"');
-            methods.push(method_str.to_string());
+            return syn::Error::new_spanned(&arg.path, "expected `path` or `method`").to_compile_error().into();
         }
     }
 
-    // checks if the methods are of valid type
+    // checks if the methods are valid method names. Besides the standard ones any token works, like WebDAV's PROPFIND
 
     for method in methods.clone() {
-        match method.as_str() {
-            "GET" | "POST" | "PUT" | "DELETE" | "OPTIONS" => {},
-            _ => panic!("You are using method: {}, which is not a valid method", method),
+        if !lib_shared::request::is_token(&method) {
+            panic!("You are using method: {:?}, which is not a valid method", method);
         }
     }
 
+    let arguments = match arguments(&handler, &path) {
+        Ok(arguments) => arguments,
+        Err(e)        => return e.to_compile_error().into(),
+    };
+
     // Use the handler name to create a unique module name
     let module_name = format_ident!("route_{}", handler_name);     
 
@@ -82,12 +104,18 @@ pub fn route(attr: TokenStream, item: TokenStream) -> TokenStream {
             use lib_shared::*;
             use ctor::ctor;            
 
+            // reads the arguments of the handler from the request. The handler is named through super, so a handler
+            // called like one of the items of this module is still the one that is called
+            fn __orangutan_handle(request: &Request) -> Response {
+                super::#handler_name(#(#arguments),*)
+            }
+
             #[ctor]
-            fn register_route() { // adds the defined ruote to ROUTES where it can be read from elsewhere of the code 
+            fn __orangutan_register_route() { // adds the defined ruote to ROUTES where it can be read from elsewhere of the code 
                 let route_info = RouteInfo::new(
                     #path.to_string(),
                     vec![#(#methods.to_string()),*],
-                    #handler_name as fn(&Request) -> Response,                                        
+                    __orangutan_handle as fn(&Request) -> Response,                                        
                 );
                 add_route(route_info);
             }
@@ -95,4 +123,96 @@ pub fn route(attr: TokenStream, item: TokenStream) -> TokenStream {
     };
 
     TokenStream::from(expanded)
-}
\ No newline at end of file
+}
+
+/// returns the expressions the handler is called with: the request itself for a `&Request` argument,
+/// and the value of the placeholder with the same name for any other argument.
+fn arguments(handler: &ItemFn, path: &str) -> Result<Vec<proc_macro2::TokenStream>, syn::Error> {
+    let placeholders = lib_shared::placeholders(path);
+    let mut arguments = Vec::new();
+
+    for input in &handler.sig.inputs {
+        let input = match input {
+            FnArg::Typed(input) => input,
+            FnArg::Receiver(receiver) => return Err(syn::Error::new_spanned(receiver, "a handler can't take self")),
+        };
+
+        if is_request(&input.ty) {
+            arguments.push(quote! { request });
+            continue;
+        }
+
+        let name = match &*input.pat {
+            Pat::Ident(ident) => ident.ident.to_string(),
+            other => return Err(syn::Error::new_spanned(other, "path variables must be plain names, like `id: u32`")),
+        };
+
+        let placeholder = placeholders.iter().find(|p| p.name == name).ok_or_else(|| {
+            let names: Vec<&str> = placeholders.iter().map(|p| p.name.as_str()).collect();
+
+            let help = if names.is_empty() {
+                String::from("the path has no variables")
+            } else {
+                format!("the path has: {}", names.join(", "))
+            };
+
+            syn::Error::new_spanned(&input.pat, format!("`{}` is not a variable of \"{}\", {}", name, path, help))
+        })?;
+
+        check_type(placeholder, &input.ty)?;
+
+        let ty = &input.ty;
+
+        arguments.push(quote! { request.get_var::<#ty>(#name) });
+    }
+
+    Ok(arguments)
+}
+
+/// true if the type is `&Request`.
+fn is_request(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => type_name(&reference.elem).as_deref() == Some("Request"),
+        _                          => false,
+    }
+}
+
+/// returns the last segment of a type's path, like `NaiveDate` for `chrono::NaiveDate`.
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|segment| segment.ident.to_string()),
+        _                => None,
+    }
+}
+
+/// Checks that the values a built-in converter matches can be read as `ty`. A String can hold any of them.
+/// Converters added with `OrangutanBuilder::converter` aren't known here, their types only need to implement FromUri.
+fn check_type(placeholder: &Placeholder, ty: &Type) -> Result<(), syn::Error> {
+    const SIGNED: &[&str] = &["i8", "i16", "i32", "i64", "i128", "isize"];
+    const UNSIGNED: &[&str] = &["u8", "u16", "u32", "u64", "u128", "usize"];
+
+    let accepted: Vec<&str> = match placeholder.converter.as_str() {
+        "int"                                    => SIGNED.to_vec(),
+        "uint"                                   => SIGNED.iter().chain(UNSIGNED).copied().collect(),
+        "float"                                  => vec!["f32", "f64"],
+        "bool"                                   => vec!["bool"],
+        "date"                                   => vec!["NaiveDate"],
+        "str" | "path" | "slug" | "any"          => vec!["char"],
+        "uuid"                                   => Vec::new(),
+        _                                        => return Ok(()),
+    };
+
+    let name = type_name(ty).unwrap_or_default();
+
+    if name == "String" || accepted.contains(&name.as_str()) {
+        return Ok(());
+    }
+
+    let mut expected = accepted;
+    expected.push("String");
+
+    Err(syn::Error::new_spanned(ty, format!(
+        "`{}` is a <{}:{}> in the path, which can't be read as `{}`; use one of {}",
+        placeholder.name, placeholder.converter, placeholder.name, quote!(#ty), expected.join(", ")
+    )))
+}
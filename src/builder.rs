@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use lib_shared::cookie::Cookie;
+use lib_shared::request::Request;
+use lib_shared::response::Response;
+use mio::tcp::TcpListener;
+
+use crate::converter::{Converter, Converters};
+use crate::framing::Limits;
+use crate::session::{CookieSessions, SessionStore, Sessions, SharedStore, StoreSessions};
+use crate::{Orangutan, OrangutanError, Phase};
+
+/// Everything about the server that can be tuned with the OrangutanBuilder.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub workers:         usize,
+    pub max_connections: usize,
+    pub read_buffer:     usize,
+    pub limits:          Limits,
+    pub keep_alive:      Duration,
+    pub header_timeout:  Duration,
+    pub body_timeout:    Duration,
+    pub write_timeout:   Duration,
+    pub max_requests:    usize,
+    pub shutdown:        Duration,
+    pub errors:          HashMap<u16, fn(&Request) -> Response>,
+    pub converters:      Converters,
+    pub secret_key:      Option<Vec<u8>>,
+    pub fallback_keys:   Vec<Vec<u8>>,
+    pub session_cookie:  Cookie,
+    pub session_store:   Option<SharedStore>,
+    pub session_idle:    Duration,
+    pub session_life:    Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            workers:         255,
+            max_connections: 2048,
+            read_buffer:     2048,
+            limits:          Limits { max_head: 8 * 1024, max_body: 16 * 1024 * 1024 },
+            keep_alive:      Duration::from_secs(5),
+            header_timeout:  Duration::from_secs(10),
+            body_timeout:    Duration::from_secs(30),
+            write_timeout:   Duration::from_secs(30),
+            max_requests:    100,
+            shutdown:        Duration::from_secs(30),
+            errors:          HashMap::new(),
+            converters:      Converters::default(),
+            secret_key:      None,
+            fallback_keys:   Vec::new(),
+            session_cookie:  crate::session::default_cookie(),
+            session_store:   None,
+            session_idle:    Duration::from_secs(30 * 60),
+            session_life:    Duration::from_secs(8 * 3600),
+        }
+    }
+}
+
+impl Config {
+    /// How long a connection may stay in a phase.
+    pub fn timeout(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Head  => self.header_timeout,
+            Phase::Body  => self.body_timeout,
+            Phase::Idle  => self.keep_alive,
+            Phase::Write => self.write_timeout,
+        }
+    }
+
+    /// Where `request.session` is kept: in the session store if there is one, in a signed cookie otherwise.
+    pub fn sessions(&self) -> Sessions {
+        match &self.session_store {
+            Some(store) => Sessions::Store(StoreSessions::new(store.clone(), self.session_cookie.clone(), self.session_idle, self.session_life)),
+            None        => Sessions::Cookie(CookieSessions::new(self.secret_key.clone(), self.fallback_keys.clone(), self.session_cookie.clone())),
+        }
+    }
+}
+
+/// Configures an Orangutan before it starts. Every setting has a default, so only the addresses to listen on are needed.
+///
+/// ```no_run
+/// # use orangutan::Orangutan;
+/// # use std::time::Duration;
+/// let mut app = Orangutan::builder()
+///     .bind("127.0.0.1:8080")
+///     .bind("[::1]:8080")
+///     .workers(64)
+///     .max_connections(10_000)
+///     .max_body_size(64 * 1024 * 1024)
+///     .keep_alive_timeout(Duration::from_secs(30))
+///     .build()?;
+///
+/// app.run()?;
+/// # Ok::<(), orangutan::OrangutanError>(())
+/// ```
+
+#[derive(Debug, Default)]
+pub struct OrangutanBuilder {
+    addresses: Vec<Result<Vec<SocketAddr>, std::io::Error>>,
+    config:    Config,
+}
+
+impl OrangutanBuilder {
+    pub fn new() -> OrangutanBuilder {
+        OrangutanBuilder::default()
+    }
+
+    /// Adds an address to listen on. Can be called more than once to listen on several addresses.
+    /// Every address a name resolves to is tried until one can be bound.
+    pub fn bind<A: ToSocketAddrs>(mut self, address: A) -> Self {
+        self.addresses.push(address.to_socket_addrs().map(|addrs| addrs.collect()));
+        self
+    }
+
+    /// Sets how many handlers can run at the same time. Defaults to 255.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers.max(1);
+        self
+    }
+
+    /// Sets how many connections can be open at the same time. Defaults to 2048.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.config.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Sets how many bytes are read from a connection at a time. Defaults to 2048.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.config.read_buffer = size.max(1);
+        self
+    }
+
+    /// Sets the largest request line and headers (in bytes) the server accepts. Bigger requests are answered with 413,
+    /// or 414 when the request line alone doesn't fit. Defaults to 8 KiB.
+    ///
+    /// However large this is set, a request target longer than `lib_shared::request::MAX_URI_LENGTH` (4 KiB) is answered with 414.
+    pub fn max_head_size(mut self, size: usize) -> Self {
+        self.config.limits.max_head = size;
+        self
+    }
+
+    /// Sets the largest request body (in bytes) the server accepts. Bigger requests are answered with 413. Defaults to 16 MiB.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.config.limits.max_body = size;
+        self
+    }
+
+    /// Sets how long an idle keep-alive connection is kept open before it is closed. Defaults to 5 seconds.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.config.keep_alive = timeout;
+        self
+    }
+
+    /// Sets how long a client has to send the request line and headers, counted from the first byte
+    /// (or from connecting). Slower clients are answered with 408. Defaults to 10 seconds.
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.config.header_timeout = timeout;
+        self
+    }
+
+    /// Sets how long the body of a request may stall before the client is answered with 408.
+    /// The timer starts over whenever more of the body arrives. Defaults to 30 seconds.
+    pub fn body_timeout(mut self, timeout: Duration) -> Self {
+        self.config.body_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a client may stop reading the response before the connection is closed.
+    /// The timer starts over whenever the client takes more of it. Defaults to 30 seconds.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// Sets how many requests a single connection can serve before it is closed. Defaults to 100.
+    ///
+    /// Setting this to 1 turns keep-alive off.
+    pub fn max_requests_per_connection(mut self, max_requests: usize) -> Self {
+        self.config.max_requests = max_requests.max(1);
+        self
+    }
+
+    /// Sets how long a shutdown waits for running handlers and unsent responses before giving up on them. Defaults to 30 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.config.shutdown = timeout;
+        self
+    }
+
+    /// Replaces the response sent for an error status: 404 when no route matches, 405 when the route doesn't take the method,
+    /// 500 when a handler panics, and the errors the server answers by itself (400, 408, 413, 414, 501, 503 and 505).
+    ///
+    /// ```no_run
+    /// # use orangutan::Orangutan;
+    /// # use lib_shared::request::Request;
+    /// # use lib_shared::response::Response;
+    /// fn oops(request: &Request) -> Response {
+    ///     let mut res = Response::new();
+    ///     res.set_status(500);
+    ///     res.insert(format!("<h1>Something went wrong on {}</h1>", request.path));
+    ///     res
+    /// }
+    ///
+    /// let mut app = Orangutan::builder()
+    ///     .bind("127.0.0.1:8080")
+    ///     .error_handler(500, oops)
+    ///     .build()?;
+    /// # Ok::<(), orangutan::OrangutanError>(())
+    /// ```
+    pub fn error_handler(mut self, status: u16, handler: fn(&Request) -> Response) -> Self {
+        self.config.errors.insert(status, handler);
+        self
+    }
+
+    /// Adds a converter that routes can use as `<name:var>`, or replaces a built-in one. See `Converter`.
+    pub fn converter<C: Converter + 'static>(mut self, name: &str, converter: C) -> Self {
+        self.config.converters.insert(name, converter);
+        self
+    }
+
+    /// The key `request.session` is signed with. Without one, or with an empty one, sessions only last for the request.
+    ///
+    /// Anyone with the key can make sessions the server trusts, so it should be long, random and kept out of the code,
+    /// like 32 random bytes read from the environment:
+    ///
+    /// ```no_run
+    /// # use orangutan::Orangutan;
+    /// let mut app = Orangutan::builder()
+    ///     .bind("127.0.0.1:8080")
+    ///     .secret_key(std::env::var("SECRET_KEY").expect("SECRET_KEY is not set"))
+    ///     .fallback_key(std::env::var("OLD_SECRET_KEY").unwrap_or_default())
+    ///     .build()?;
+    /// # Ok::<(), orangutan::OrangutanError>(())
+    /// ```
+    pub fn secret_key<K: AsRef<[u8]>>(mut self, key: K) -> Self {
+        self.config.secret_key = Some(key.as_ref().to_vec()).filter(|key| !key.is_empty());
+        self
+    }
+
+    /// A previous secret key that sessions are still accepted with, so changing the key doesn't log everyone out.
+    /// Sessions signed with it are signed again with the secret key. Can be called more than once, an empty key is ignored.
+    pub fn fallback_key<K: AsRef<[u8]>>(mut self, key: K) -> Self {
+        if !key.as_ref().is_empty() {
+            self.config.fallback_keys.push(key.as_ref().to_vec());
+        }
+
+        self
+    }
+
+    /// The name and attributes of the session cookie. Its Max-Age also limits how old a session the server accepts.
+    /// The default is `Cookie::new("session", "").path("/").http_only(true).same_site(SameSite::Lax)`.
+    pub fn session_cookie(mut self, cookie: Cookie) -> Self {
+        self.config.session_cookie = cookie;
+        self
+    }
+
+    /// Keeps sessions in `store` instead of in a signed cookie. The session cookie then only holds a random id,
+    /// so sessions can be as large as the store allows and clients can't read them. No secret key is needed.
+    ///
+    /// ```no_run
+    /// # use orangutan::Orangutan;
+    /// # use orangutan::session::FileStore;
+    /// # use std::time::Duration;
+    /// let mut app = Orangutan::builder()
+    ///     .bind("127.0.0.1:8080")
+    ///     .session_store(FileStore::new("/var/lib/myapp/sessions")?)
+    ///     .session_idle_timeout(Duration::from_secs(15 * 60))
+    ///     .build()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn session_store<S: SessionStore + 'static>(mut self, store: S) -> Self {
+        self.config.session_store = Some(SharedStore(std::sync::Arc::new(store)));
+        self
+    }
+
+    /// How long a stored session lasts without being used (30 min).
+    pub fn session_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.session_idle = timeout;
+        self
+    }
+
+    /// How long a stored session lasts after it was created, however much it is used (8 h).
+    pub fn session_lifetime(mut self, lifetime: Duration) -> Self {
+        self.config.session_life = lifetime;
+        self
+    }
+
+    /// Binds every address and creates the Orangutan.
+    pub fn build(self) -> Result<Orangutan, OrangutanError> {
+        if self.addresses.is_empty() {
+            return Err(OrangutanError::Bind(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no address to listen on".to_string()
+            )));
+        }
+
+        let mut listeners: Vec<TcpListener> = Vec::new();
+
+        for addresses in self.addresses {
+            listeners.push(bind(&addresses.map_err(OrangutanError::Resolve)?)?);
+        }
+
+        Ok(Orangutan::with_config(listeners, self.config))
+    }
+}
+
+fn bind(addresses: &[SocketAddr]) -> Result<TcpListener, OrangutanError> {
+    let mut last_error = std::io::Error::new(
+        std::io::ErrorKind::AddrNotAvailable,
+        "address did not resolve to anything".to_string()
+    );
+
+    for addr in addresses {
+        match TcpListener::bind(addr) {
+            Ok(server)  => return Ok(server),
+            Err(e)      => last_error = e,
+        }
+    }
+
+    Err(OrangutanError::Bind(last_error))
+}
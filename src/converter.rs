@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides which values a `<converter:name>` segment of a route accepts, and what the handler gets for them.
+///
+/// The built-in converters are int, uint, float, str, path, uuid, slug, bool, date and any.
+/// Others can be added with `OrangutanBuilder::converter`:
+///
+/// ```no_run
+/// # use orangutan::{Converter, Orangutan};
+/// struct Hex;
+///
+/// impl Converter for Hex {
+///     fn matches(&self, value: &str, _args: &[String]) -> bool {
+///         value.chars().all(|c| c.is_ascii_hexdigit())
+///     }
+///
+///     // the handler gets the value as a decimal number, ready for `request.get_var::<u32>("color")`
+///     fn convert(&self, value: &str, _args: &[String]) -> String {
+///         u64::from_str_radix(value, 16).map(|n| n.to_string()).unwrap_or_default()
+///     }
+/// }
+///
+/// // #[route(path="/colors/<hex:color>", method="[GET]")]
+/// let mut app = Orangutan::builder()
+///     .bind("127.0.0.1:8080")
+///     .converter("hex", Hex)
+///     .build()?;
+/// # Ok::<(), orangutan::OrangutanError>(())
+/// ```
+pub trait Converter: Send + Sync {
+    /// true if `value` belongs in the segment. `args` are the arguments given in the route, like `json` and `xml` in `<any(json,xml):format>`.
+    fn matches(&self, value: &str, args: &[String]) -> bool;
+
+    /// The value stored in `request.params` for a matching segment. Defaults to the segment as it was sent.
+    fn convert(&self, value: &str, _args: &[String]) -> String {
+        value.to_string()
+    }
+
+    /// How specific the converter is when routes compete for a path: the lower, the sooner it is tried.
+    /// Static segments are 0, int, uint and float are 1, str is 2 and path is 3. Defaults to 1.
+    fn rank(&self) -> u8 {
+        1
+    }
+
+    /// true if the value can take up more than one segment, like path does. Defaults to false.
+    fn spans_segments(&self) -> bool {
+        false
+    }
+}
+
+/// The converters routes can use, by name.
+#[derive(Clone)]
+pub struct Converters {
+    converters: HashMap<String, Arc<dyn Converter>>,
+}
+
+impl Converters {
+    /// Adds a converter, replacing the one with the same name.
+    pub fn insert<C: Converter + 'static>(&mut self, name: &str, converter: C) {
+        self.converters.insert(name.to_string(), Arc::new(converter));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Converter>> {
+        self.converters.get(name).cloned()
+    }
+}
+
+impl Default for Converters {
+    fn default() -> Converters {
+        let mut converters = Converters { converters: HashMap::new() };
+
+        converters.insert("int", Int);
+        converters.insert("uint", Uint);
+        converters.insert("float", Float);
+        converters.insert("str", Str);
+        converters.insert("path", Path);
+        converters.insert("uuid", Uuid);
+        converters.insert("slug", Slug);
+        converters.insert("bool", Bool);
+        converters.insert("date", Date);
+        converters.insert("any", Any);
+
+        converters
+    }
+}
+
+impl fmt::Debug for Converters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.converters.keys().collect();
+        names.sort();
+
+        f.debug_tuple("Converters").field(&names).finish()
+    }
+}
+
+fn digits(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The value without its minus sign. Only one is taken off, so `--1` is still not a number.
+fn unsigned(value: &str) -> &str {
+    value.strip_prefix('-').unwrap_or(value)
+}
+
+/// A whole number, optionally negative.
+pub struct Int;
+
+impl Converter for Int {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        digits(unsigned(value))
+    }
+}
+
+/// A whole number that isn't negative.
+pub struct Uint;
+
+impl Converter for Uint {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        digits(value)
+    }
+}
+
+/// A decimal number like `1.5`, `.5` or `3`, optionally negative.
+pub struct Float;
+
+impl Converter for Float {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        let value = unsigned(value);
+
+        match value.split_once('.') {
+            Some((whole, fraction)) => (whole.is_empty() || digits(whole)) && digits(fraction),
+            None                    => digits(value),
+        }
+    }
+}
+
+/// Any single segment. Used when a parameter has no converter.
+pub struct Str;
+
+impl Converter for Str {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        !value.is_empty() && !value.contains('/')
+    }
+
+    fn rank(&self) -> u8 {
+        2
+    }
+}
+
+/// The rest of the path, slashes included.
+pub struct Path;
+
+impl Converter for Path {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        !value.is_empty()
+    }
+
+    fn rank(&self) -> u8 {
+        3
+    }
+
+    fn spans_segments(&self) -> bool {
+        true
+    }
+}
+
+/// A UUID like `7c9e6679-7425-40de-944b-e07fc1f90ae7`, in either case. The handler gets it in lowercase.
+pub struct Uuid;
+
+impl Converter for Uuid {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        value.len() == 36 && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _                => c.is_ascii_hexdigit(),
+        })
+    }
+
+    fn convert(&self, value: &str, _args: &[String]) -> String {
+        value.to_ascii_lowercase()
+    }
+}
+
+/// Letters, digits, hyphens and underscores, like `my-first-post`.
+pub struct Slug;
+
+impl Converter for Slug {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    }
+}
+
+/// `true`, `false`, `1` or `0`. The handler gets `true` or `false`.
+pub struct Bool;
+
+impl Converter for Bool {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        matches!(value, "true" | "false" | "1" | "0")
+    }
+
+    fn convert(&self, value: &str, _args: &[String]) -> String {
+        String::from(if value == "true" || value == "1" { "true" } else { "false" })
+    }
+}
+
+/// A calendar date like `2024-02-29`.
+pub struct Date;
+
+impl Converter for Date {
+    fn matches(&self, value: &str, _args: &[String]) -> bool {
+        let parts: Vec<&str> = value.split('-').collect();
+
+        if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 || !parts.iter().all(|p| digits(p)) {
+            return false;
+        }
+
+        let year: u32 = parts[0].parse().unwrap_or(0);
+        let month: u32 = parts[1].parse().unwrap_or(0);
+        let day: u32 = parts[2].parse().unwrap_or(0);
+
+        let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+
+        let days = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11              => 30,
+            2 if leap                   => 29,
+            2                           => 28,
+            _                           => return false,
+        };
+
+        (1..=days).contains(&day)
+    }
+}
+
+/// One of the values listed in the route, like `<any(json,xml):format>`.
+pub struct Any;
+
+impl Converter for Any {
+    fn matches(&self, value: &str, args: &[String]) -> bool {
+        args.iter().any(|arg| arg == value)
+    }
+}
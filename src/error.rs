@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// Everything that can keep an Orangutan from starting or running.
+#[derive(Debug)]
+pub enum OrangutanError {
+    /// The address given to `Orangutan::new` could not be resolved.
+    Resolve(std::io::Error),
+    /// The listener could not be bound, for example because the port is already in use.
+    Bind(std::io::Error),
+    /// The event loop could not be created, or it stopped with an error.
+    EventLoop(std::io::Error),
+    /// A route could not be registered, for example because the same path and method were defined twice.
+    Route(String),
+}
+
+impl fmt::Display for OrangutanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrangutanError::Resolve(e)   => write!(f, "unable to resolve the address: {}", e),
+            OrangutanError::Bind(e)      => write!(f, "unable to bind the server: {}", e),
+            OrangutanError::EventLoop(e) => write!(f, "event loop failed: {}", e),
+            OrangutanError::Route(msg)   => write!(f, "unable to register route: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OrangutanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OrangutanError::Resolve(e) | OrangutanError::Bind(e) | OrangutanError::EventLoop(e) => Some(e),
+            OrangutanError::Route(_) => None,
+        }
+    }
+}
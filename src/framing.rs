@@ -0,0 +1,480 @@
+/// What the input buffer of a Client holds at the moment.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Frame {
+    /// The head has not fully arrived yet, keep reading.
+    Head,
+    /// The head has arrived but the body has not, keep reading.
+    Body,
+    /// A whole request. The head (request line and headers) is `buf[..head]` and the body is `buf[head..end]`.
+    Complete { head: usize, end: usize },
+    /// A whole request with a chunked body. The head is `buf[..head]` and the request ends at `buf[end]`.
+    /// The body has already been decoded and the trailers are lowercased like the other headers.
+    Chunked { head: usize, end: usize, body: Vec<u8>, trailers: Vec<(String, String)> },
+}
+
+/// Why the bytes in the input buffer can never become a valid request.
+#[derive(PartialEq, Eq, Debug)]
+pub enum FrameError {
+    /// The head, the Content-Length header or the chunked body is malformed. Answered with 400.
+    BadRequest,
+    /// The head or the body is over the configured limits. Answered with 413.
+    TooLarge,
+    /// The request line alone is over the head limit. Answered with 414.
+    UriTooLong,
+    /// The body uses a transfer coding other than chunked. Answered with 501.
+    Unsupported,
+}
+
+impl FrameError {
+    pub fn status(&self) -> u16 {
+        match self {
+            FrameError::BadRequest  => 400,
+            FrameError::TooLarge    => 413,
+            FrameError::UriTooLong  => 414,
+            FrameError::Unsupported => 501,
+        }
+    }
+}
+
+/// The biggest request the server is willing to buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_head: usize,
+    pub max_body: usize,
+}
+
+impl Limits {
+    /// How many bytes of a single request can be buffered, leaving room for the chunk size lines and trailers of a chunked body.
+    /// Limits too large to add up mean there is no limit rather than overflowing.
+    pub fn max_request(&self) -> usize {
+        self.max_head.saturating_add(self.max_body).saturating_mul(2)
+    }
+}
+
+/// How far the chunked body at the start of a Client's input buffer has been walked.
+/// Kept between reads so every chunk size line is only looked at once, however many reads the body takes.
+/// Starts over with `Chunks::default()` once the request has been taken out of the buffer.
+#[derive(Debug, Default)]
+pub struct Chunks {
+    /// Where the next chunk size line (or the trailers, once the last chunk was seen) starts, 0 before the first one.
+    pos:    usize,
+    /// The data of every chunk walked so far, as ranges of the buffer.
+    ranges: Vec<(usize, usize)>,
+    /// The size of the body so far.
+    total:  usize,
+    /// The last chunk has been seen and only the trailers are left.
+    last:   bool,
+}
+
+/// Checks if `buf` starts with a whole request.
+///
+/// The head is read up to the blank line first, then either exactly `Content-Length` bytes of body
+/// or a chunked body up to its last chunk and trailers. Anything after that belongs to the next request on the connection.
+/// Requests that send both headers are rejected, since a proxy in front of the server might read the body the other way.
+///
+/// `chunks` carries the progress through a chunked body from one call to the next for the same buffer.
+pub fn frame(buf: &[u8], limits: &Limits, chunks: &mut Chunks) -> Result<Frame, FrameError> {
+    let head = match find_head_end(buf) {
+        Some(end) if end > limits.max_head => return Err(head_too_large(buf, limits)),
+        Some(end)                          => end,
+        None if buf.len() > limits.max_head => return Err(head_too_large(buf, limits)),
+        None                               => return Ok(Frame::Head),
+    };
+
+    let length = content_length(&buf[..head])?;
+    let chunked = transfer_encoding(&buf[..head])?;
+
+    if chunked {
+        if length.is_some() {
+            return Err(FrameError::BadRequest);
+        }
+
+        return match decode_chunked(buf, head, limits, chunks)? {
+            Some((end, body, trailers)) => Ok(Frame::Chunked { head, end, body, trailers }),
+            None if buf.len() >= limits.max_request() => Err(FrameError::TooLarge),
+            None => Ok(Frame::Body),
+        };
+    }
+
+    let length = length.unwrap_or(0);
+
+    if length > limits.max_body {
+        return Err(FrameError::TooLarge);
+    }
+
+    let end = head.checked_add(length).ok_or(FrameError::TooLarge)?;
+
+    if buf.len() < end {
+        return Ok(Frame::Body);
+    }
+
+    Ok(Frame::Complete { head, end })
+}
+
+/// A head over the limit that hasn't even finished its request line is blamed on the URI.
+fn head_too_large(buf: &[u8], limits: &Limits) -> FrameError {
+    match buf.windows(2).position(|window| window == b"\r\n") {
+        Some(line) if line <= limits.max_head => FrameError::TooLarge,
+        _                                     => FrameError::UriTooLong,
+    }
+}
+
+/// Returns the index right after the blank line that ends the head.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+       .position(|window| window == b"\r\n\r\n")
+       .map(|pos| pos + 4)
+}
+
+/// Returns the values of every header called `name` in the head, trimmed.
+fn header_values<'a>(head: &'a [u8], name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+    head.split(|&b| b == b'\n')
+        .skip(1)
+        .filter_map(move |line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let colon = line.iter().position(|&b| b == b':')?;
+
+            if line[..colon].eq_ignore_ascii_case(name.as_bytes()) {
+                Some(line[colon + 1..].trim_ascii())
+            } else {
+                None
+            }
+        })
+}
+
+/// Reads the Content-Length header from the head. Repeated headers have to agree with each other.
+fn content_length(head: &[u8]) -> Result<Option<usize>, FrameError> {
+    let mut length: Option<usize> = None;
+
+    for value in header_values(head, "content-length") {
+        if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+            return Err(FrameError::BadRequest);
+        }
+
+        let parsed = std::str::from_utf8(value).ok()
+                                               .and_then(|value| value.parse::<usize>().ok())
+                                               .ok_or(FrameError::TooLarge)?;
+
+        match length {
+            Some(previous) if previous != parsed => return Err(FrameError::BadRequest),
+            _ => length = Some(parsed),
+        }
+    }
+
+    Ok(length)
+}
+
+/// Returns true if the body is chunked. Chunked is the only transfer coding the server can decode.
+fn transfer_encoding(head: &[u8]) -> Result<bool, FrameError> {
+    let mut codings: Vec<Vec<u8>> = Vec::new();
+
+    for value in header_values(head, "transfer-encoding") {
+        for coding in value.split(|&b| b == b',') {
+            let coding = coding.trim_ascii();
+
+            if !coding.is_empty() {
+                codings.push(coding.to_ascii_lowercase());
+            }
+        }
+    }
+
+    match codings.as_slice() {
+        []                               => Ok(false),
+        [coding] if coding == b"chunked" => Ok(true),
+        _                                => Err(FrameError::Unsupported),
+    }
+}
+
+/// Walks the chunks that start at `buf[start]`, picking up where `chunks` left off. Returns where the request ends,
+/// the decoded body and the trailers, or None if the last chunk has not arrived yet.
+///
+/// Only the chunk size lines are looked at until the whole body is there, and each of them only once,
+/// so reading a big body in many small reads stays cheap.
+#[allow(clippy::type_complexity)]
+fn decode_chunked(buf: &[u8], start: usize, limits: &Limits, chunks: &mut Chunks) -> Result<Option<(usize, Vec<u8>, Vec<(String, String)>)>, FrameError> {
+    if chunks.pos == 0 {
+        chunks.pos = start;
+    }
+
+    while !chunks.last {
+        let pos = chunks.pos;
+        let line = match read_line(buf, pos, limits.max_head)? {
+            Some(line) => line,
+            None       => return Ok(None),
+        };
+
+        let size = chunk_size(&buf[pos..line])?;
+        let data = line + 2;
+
+        if size == 0 {
+            chunks.pos = data;
+            chunks.last = true;
+            break;
+        }
+
+        let total = chunks.total.checked_add(size).ok_or(FrameError::TooLarge)?;
+
+        if total > limits.max_body {
+            return Err(FrameError::TooLarge);
+        }
+
+        if buf.len().saturating_sub(data) < size.saturating_add(2) {
+            return Ok(None);
+        }
+
+        if &buf[data + size..data + size + 2] != b"\r\n" {
+            return Err(FrameError::BadRequest);
+        }
+
+        chunks.ranges.push((data, data + size));
+        chunks.total = total;
+        chunks.pos = data + size + 2;
+    }
+
+    // the trailers are only parsed once all of them are there, they are limited like the head
+    let trailer_start = chunks.pos;
+    let mut pos = trailer_start;
+    let mut trailers: Vec<(String, String)> = Vec::new();
+
+    loop {
+        let line = match read_line(buf, pos, limits.max_head)? {
+            Some(line) => line,
+            None       => return Ok(None),
+        };
+
+        if line - trailer_start > limits.max_head {
+            return Err(FrameError::TooLarge);
+        }
+
+        let field = &buf[pos..line];
+        pos = line + 2;
+
+        if field.is_empty() {
+            break;
+        }
+
+        let trailer = std::str::from_utf8(field).ok()
+                                                .and_then(|field| field.split_once(':'))
+                                                .ok_or(FrameError::BadRequest)?;
+
+        trailers.push((trailer.0.trim().to_lowercase(), trailer.1.trim().to_string()));
+    }
+
+    let mut body: Vec<u8> = Vec::with_capacity(chunks.total);
+
+    for &(from, to) in &chunks.ranges {
+        body.extend_from_slice(&buf[from..to]);
+    }
+
+    Ok(Some((pos, body, trailers)))
+}
+
+/// Returns the index of the CRLF ending the line that starts at `buf[pos]`, or None if it has not arrived yet.
+fn read_line(buf: &[u8], pos: usize, max_line: usize) -> Result<Option<usize>, FrameError> {
+    let rest = &buf[pos.min(buf.len())..];
+
+    match rest.windows(2).position(|window| window == b"\r\n") {
+        Some(offset) => Ok(Some(pos + offset)),
+        None if rest.len() > max_line => Err(FrameError::BadRequest),
+        None => Ok(None),
+    }
+}
+
+/// Parses the hexadecimal size of a chunk, ignoring any chunk extensions after a `;`.
+fn chunk_size(line: &[u8]) -> Result<usize, FrameError> {
+    let size = line.split(|&b| b == b';').next().unwrap_or(line).trim_ascii();
+
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(FrameError::BadRequest);
+    }
+
+    std::str::from_utf8(size).ok()
+                             .and_then(|size| usize::from_str_radix(size, 16).ok())
+                             .ok_or(FrameError::TooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits { max_head: 1024, max_body: 64 };
+
+    #[test]
+    fn head_without_body() {
+        let buf = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Ok(Frame::Complete { head: buf.len(), end: buf.len() }));
+    }
+
+    #[test]
+    fn content_length_body() {
+        let buf = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Ok(Frame::Complete { head: buf.len() - 5, end: buf.len() }));
+    }
+
+    #[test]
+    fn next_request_is_left_in_the_buffer() {
+        let buf = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET / HTTP/1.1\r\n\r\n";
+        let head = 38;
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Ok(Frame::Complete { head, end: head + 2 }));
+    }
+
+    #[test]
+    fn split_head_and_body_wait_for_more() {
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let head = request.len() - 5;
+
+        for split in 1..head {
+            assert_eq!(frame(&request[..split], &LIMITS, &mut Chunks::default()), Ok(Frame::Head), "split at {}", split);
+        }
+
+        for split in head..request.len() {
+            assert_eq!(frame(&request[..split], &LIMITS, &mut Chunks::default()), Ok(Frame::Body), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn repeated_content_length_must_agree() {
+        let same = b"POST / HTTP/1.1\r\nContent-Length: 2\r\ncontent-length: 2\r\n\r\nhi";
+        let different = b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nhi!";
+
+        assert_eq!(frame(same, &LIMITS, &mut Chunks::default()), Ok(Frame::Complete { head: same.len() - 2, end: same.len() }));
+        assert_eq!(frame(different, &LIMITS, &mut Chunks::default()), Err(FrameError::BadRequest));
+    }
+
+    #[test]
+    fn malformed_content_length_is_rejected() {
+        for value in ["", "-1", "+5", "5 5", "0x10", "1,1"] {
+            let buf = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", value);
+
+            assert_eq!(frame(buf.as_bytes(), &LIMITS, &mut Chunks::default()), Err(FrameError::BadRequest), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn content_length_with_chunked_is_rejected() {
+        let buf = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Err(FrameError::BadRequest));
+    }
+
+    #[test]
+    fn codings_other_than_chunked_are_unsupported() {
+        for value in ["gzip", "gzip, chunked", "chunked, chunked"] {
+            let buf = format!("POST / HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n", value);
+
+            assert_eq!(frame(buf.as_bytes(), &LIMITS, &mut Chunks::default()), Err(FrameError::Unsupported), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn chunked_body_is_decoded() {
+        let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n3\r\nabc\r\nA;name=value\r\n0123456789\r\n0;last\r\n\r\n";
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Ok(Frame::Chunked {
+            head:     47,
+            end:      buf.len(),
+            body:     b"abc0123456789".to_vec(),
+            trailers: Vec::new(),
+        }));
+    }
+
+    #[test]
+    fn unfinished_chunked_body_waits_for_more() {
+        let request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+
+        for split in 47..request.len() {
+            assert_eq!(frame(&request[..split], &LIMITS, &mut Chunks::default()), Ok(Frame::Body), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn chunked_body_read_in_small_pieces_resumes_where_it_stopped() {
+        let request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\nA;name=value\r\n0123456789\r\n1\r\n!\r\n0\r\nX-Checksum: 1\r\n\r\n";
+        let mut chunks = Chunks::default();
+        let mut buf: Vec<u8> = Vec::new();
+
+        for piece in request.chunks(3) {
+            match frame(&buf, &LIMITS, &mut chunks) {
+                Ok(Frame::Head) | Ok(Frame::Body) => buf.extend_from_slice(piece),
+                other                             => panic!("{:?} after {} bytes", other, buf.len()),
+            }
+        }
+
+        assert_eq!(frame(&buf, &LIMITS, &mut chunks), Ok(Frame::Chunked {
+            head:     47,
+            end:      request.len(),
+            body:     b"abc0123456789!".to_vec(),
+            trailers: vec![(String::from("x-checksum"), String::from("1"))],
+        }));
+        assert_eq!(chunks.ranges.len(), 3);
+        assert!(chunks.last);
+    }
+
+    #[test]
+    fn malformed_chunks_are_rejected() {
+        for body in ["x\r\nabc\r\n0\r\n\r\n", "3\r\nabcd\r\n0\r\n\r\n", ";ext\r\n\r\n"] {
+            let buf = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}", body);
+
+            assert_eq!(frame(buf.as_bytes(), &LIMITS, &mut Chunks::default()), Err(FrameError::BadRequest), "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn bodies_over_the_limit_are_too_large() {
+        let length = b"POST / HTTP/1.1\r\nContent-Length: 65\r\n\r\n";
+        let huge = b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n";
+        let chunked = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n41\r\n";
+        let chunk_overflow = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffffffff\r\n";
+
+        assert_eq!(frame(length, &LIMITS, &mut Chunks::default()), Err(FrameError::TooLarge));
+        assert_eq!(frame(huge, &LIMITS, &mut Chunks::default()), Err(FrameError::TooLarge));
+        assert_eq!(frame(chunked, &LIMITS, &mut Chunks::default()), Err(FrameError::TooLarge));
+        assert_eq!(frame(chunk_overflow, &LIMITS, &mut Chunks::default()), Err(FrameError::TooLarge));
+    }
+
+    #[test]
+    fn unlimited_body_does_not_overflow() {
+        let limits = Limits { max_head: 1024, max_body: usize::MAX };
+        let length = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+        let chunked = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\nabc", usize::MAX);
+
+        assert_eq!(limits.max_request(), usize::MAX);
+        assert_eq!(Limits { max_head: usize::MAX, max_body: usize::MAX }.max_request(), usize::MAX);
+        assert_eq!(frame(length.as_bytes(), &limits, &mut Chunks::default()), Err(FrameError::TooLarge));
+        assert_eq!(frame(chunked.as_bytes(), &limits, &mut Chunks::default()), Ok(Frame::Body));
+    }
+
+    #[test]
+    fn long_head_is_too_large_and_long_request_line_too_long() {
+        let header = format!("GET / HTTP/1.1\r\nX-Filler: {}\r\n\r\n", "a".repeat(2000));
+        let uri = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(2000));
+        let unfinished_uri = format!("GET /{}", "a".repeat(2000));
+
+        assert_eq!(frame(header.as_bytes(), &LIMITS, &mut Chunks::default()), Err(FrameError::TooLarge));
+        assert_eq!(frame(uri.as_bytes(), &LIMITS, &mut Chunks::default()), Err(FrameError::UriTooLong));
+        assert_eq!(frame(unfinished_uri.as_bytes(), &LIMITS, &mut Chunks::default()), Err(FrameError::UriTooLong));
+    }
+
+    #[test]
+    fn trailers_are_returned_apart_from_the_head() {
+        let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\nCookie: session=planted\r\nX-Checksum: 1\r\n\r\n";
+        let head = 47;
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Ok(Frame::Chunked {
+            head,
+            end:      buf.len(),
+            body:     b"abc".to_vec(),
+            trailers: vec![(String::from("cookie"), String::from("session=planted")), (String::from("x-checksum"), String::from("1"))],
+        }));
+    }
+
+    #[test]
+    fn trailer_without_colon_is_rejected() {
+        let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nnot a field\r\n\r\n";
+
+        assert_eq!(frame(buf, &LIMITS, &mut Chunks::default()), Err(FrameError::BadRequest));
+    }
+}
@@ -1,64 +1,89 @@
-use std::collections::{HashMap, HashSet};
-use std::io::Write;
 use std::net::ToSocketAddrs;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 use lib_shared::{RouteInfo,utils, ROUTES};
 use lib_shared::response::Response;
-use lib_shared::request::{match_method, Method, Request};
+use lib_shared::request::{match_method, Method, ParamError, Request};
+
+use framing::{Chunks, Frame};
+use reply::Reply;
+use shutdown::ShutdownHandle;
+pub use builder::OrangutanBuilder;
+pub use converter::Converter;
+pub use error::OrangutanError;
+
+use builder::Config;
 
 use threadpool::ThreadPool;
 use mio::util::Slab;
 use mio::tcp::{TcpStream, TcpListener};
-use mio::{Token, EventSet, EventLoop, PollOpt, Sender, Handler, TryRead};
+use mio::{Token, EventSet, EventLoop, EventLoopConfig, PollOpt, Sender, Handler, Timeout, TryRead, TryWrite};
 
+pub mod builder;
+pub mod converter;
+pub mod error;
 pub mod route;
+pub mod session;
+pub mod shutdown;
+mod framing;
+mod signing;
+mod reply;
 
 struct Client {
-    sock:   TcpStream,
-    token:  Token,
-    events: EventSet,
-    i_buf:  Vec<u8>,
-    o_buf:  Vec<u8>,
+    sock:       TcpStream,
+    token:      Token,
+    serial:     u64,
+    i_buf:      Vec<u8>,
+    chunks:     Chunks,
+    o_buf:      Vec<u8>,
+    busy:       bool,
+    closed:     bool,
+    keep_alive: bool,
+    served:     usize,
+    phase:      Option<Phase>,
+    timer:      Option<Timeout>,
+    pull:       Option<mpsc::Sender<()>>,
 }
 
 impl Client {
     /// Creates a new Client instance with an initial capacity for input and output buffers.
-    fn new(sock: TcpStream, token: Token) -> Client {
+    fn new(sock: TcpStream, token: Token, serial: u64, capacity: usize) -> Client {
         Client {
             sock,
             token,
-            events: EventSet::hup(),
-            i_buf:  Vec::with_capacity(2048),
-            o_buf:  Vec::new(),
+            serial,
+            i_buf:      Vec::with_capacity(capacity),
+            chunks:     Chunks::default(),
+            o_buf:      Vec::new(),
+            busy:       false,
+            closed:     false,
+            keep_alive: false,
+            served:     0,
+            phase:      None,
+            timer:      None,
+            pull:       None,
         }
     }
 
-    /// Reads data from the socket into the input buffer. 
-    /// Returns Ok(true) if data was read successfully, otherwise Ok(false) or an Err.
-    fn receive(&mut self) -> Result<bool, std::io::Error> {
+    /// Reads data from the socket into the input buffer `size` bytes at a time, stopping early once the buffer holds `limit` bytes. 
+    /// Returns Ok(true) if data was read successfully, Ok(false) if there was nothing to read, or an Err.
+    /// Once the peer has closed its side `closed` is set, what it sent before that is still in the buffer to be answered.
+    fn receive(&mut self, size: usize, limit: usize) -> Result<bool, std::io::Error> {
         let mut bytes_read: usize = 0;
 
-        loop {
-            let mut buf: Vec<u8> = Vec::with_capacity(2048);
+        while self.i_buf.len() < limit && !self.closed {
+            let mut buf: Vec<u8> = Vec::with_capacity(size);
             match self.sock.try_read_buf(&mut buf) {
-                Ok(size)  => {
-                    match size {
-                        Some(bytes) => {
-                            self.i_buf.extend(buf);
-                            bytes_read += bytes;
-                        },
-                        None    => {
-                            self.events.remove(EventSet::readable());
-                            self.events.insert(EventSet::writable());
-                            break;
-                        },
-                    }
-                },
-                Err(_)  => {
-                    self.events.remove(EventSet::readable());
-                    self.events.insert(EventSet::writable());
-                    break;
+                Ok(Some(0))     => self.closed = true,
+                Ok(Some(bytes)) => {
+                    self.i_buf.extend(buf);
+                    bytes_read += bytes;
                 },
+                Ok(None)        => break,
+                Err(e)          => return Err(e),
             };
         }
 
@@ -66,83 +91,152 @@ impl Client {
     }
 
     /// Writes data from the output buffer to the socket.
-    /// Returns Ok(true) if data was sent successfully, Ok(false) if there's no data to send, or an Err.
+    /// Returns Ok(true) once the whole output buffer has been sent, Ok(false) if the socket would block before that, or an Err.
     fn send(&mut self) -> Result<bool, std::io::Error> {
-        if self.o_buf.is_empty() {
-            return Ok(false);
-        }
-
         while !self.o_buf.is_empty() {
-            match self.sock.write(&self.o_buf.as_slice()) {
-                Ok(sz)  => {
-                    if sz == self.o_buf.len() {
-                        // we did it!
-                        self.events.remove(EventSet::writable());
-                        break;
-                    } else {
-                        // keep going
-                        self.o_buf = self.o_buf.split_off(sz);
-                    }
+            match self.sock.try_write(&self.o_buf) {
+                Ok(Some(0))  => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        "connection stopped accepting data".to_string()
+                    ));
                 },
-                Err(_)  => {
-                    return Ok(true);
-                }
+                Ok(Some(sz)) => { self.o_buf.drain(..sz); },
+                Ok(None)     => return Ok(false),
+                Err(e)       => return Err(e),
             }
         }
 
         Ok(true)
     }
 
+    /// The events the client is interested in. A client waits for a request, waits for its handler 
+    /// (only listening for hang-ups) or waits to write the response, never more than one at a time.
+    /// A peer that already closed its side can't hang up again, or be read from.
+    fn interest(&self) -> EventSet {
+        let mut events = if self.closed { EventSet::none() } else { EventSet::hup() };
+
+        if !self.o_buf.is_empty() {
+            events.insert(EventSet::writable());
+        } else if !self.busy && !self.closed {
+            events.insert(EventSet::readable());
+        }
+
+        events
+    }
+
+    /// Starts the timer for the phase the client is in, replacing the timer of the previous phase.
+    fn arm(&mut self, evl: &mut EventLoop<Orangutan>, phase: Phase, after: Duration) {
+        self.disarm(evl);
+        self.phase = Some(phase);
+        self.timer = match evl.timeout_ms(Timer::Client(self.token, self.serial, phase), after.as_millis() as u64) {
+            Ok(timer) => Some(timer),
+            Err(e)    => {
+                eprintln!("  * unable to start the {:?} timer of a connection, it won't time out: {:?}", phase, e);
+                None
+            },
+        };
+    }
+
+    /// Stops the timer of the current phase. Used while the handler is running, which is not limited.
+    fn disarm(&mut self, evl: &mut EventLoop<Orangutan>) {
+        if let Some(timer) = self.timer.take() {
+            evl.clear_timeout(timer);
+        }
+        self.phase = None;
+    }
+
     /// Registers the client with the event loop to listen for events.
-    /// Adds the `readable` event to the client's event set.
     fn register(&mut self, evl: &mut EventLoop<Orangutan>) -> Result<(), std::io::Error> {
-        self.events.insert(EventSet::readable());
-        evl.register(&self.sock, self.token, self.events, PollOpt::edge() | PollOpt::oneshot())
+        evl.register(&self.sock, self.token, self.interest(), PollOpt::edge() | PollOpt::oneshot())
     }
 
     /// Re-registers the client with the event loop to continue listening for events.
     fn reregister(&mut self, evl: &mut EventLoop<Orangutan>) -> Result<(), std::io::Error> {
-        evl.reregister(&self.sock, self.token, self.events, PollOpt::edge() | PollOpt::oneshot())
+        evl.reregister(&self.sock, self.token, self.interest(), PollOpt::edge() | PollOpt::oneshot())
     }
 }
 
 pub struct Orangutan {
-    routes:  HashMap<route::RouteDef, route::Route>,
-    rcache:  HashMap<route::RouteDef, route::RouteDef>,
-    server:  Option<TcpListener>,
-    token:   Token,        
+    router:  route::Router,
+    servers: Vec<TcpListener>,
     conns:   Slab<Client>,
+    serial:  u64,
     default: fn(&Request) -> Response,
     tpool:   ThreadPool,
+    config:  Config,
+    shutdown: ShutdownHandle,
+    draining: bool,
+    sessions: Arc<session::Sessions>,
+}
+
+/// What a connection is waiting for. Each phase has its own timeout, so a slow or silent client can't hold a connection forever.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading the request line and headers. Runs from the first byte (or from accepting the connection) until the head is complete, however slowly it trickles in.
+    Head,
+    /// Reading the body. Starts over whenever more of the body arrives.
+    Body,
+    /// Waiting for the next request on a keep-alive connection.
+    Idle,
+    /// Writing the response. Starts over whenever the client takes more of it.
+    Write,
 }
 
+/// What a timeout scheduled on the event loop is for.
+#[derive(Clone, Copy, Debug)]
+pub enum Timer {
+    /// A connection spent too long in a phase. The serial tells connections that reused the token apart.
+    Client(Token, u64, Phase),
+    /// Time to check if a shutdown has been asked for.
+    Watch,
+    /// The process ran out of file descriptors, time to try accepting on this listener again.
+    Listen(Token),
+    /// The handlers still running after a shutdown was asked for ran out of time.
+    Deadline,
+    /// Time to remove the expired sessions from the SessionStore.
+    Purge,
+}
+
+/// How often the event loop checks the ShutdownHandle.
+const WATCH_INTERVAL_MS: u64 = 100;
+
+/// How long a listener waits before accepting again after the process ran out of file descriptors.
+const ACCEPT_BACKOFF_MS: u64 = 500;
+
+/// What clients turned away because every connection slot is taken are told to wait, in seconds.
+const RETRY_AFTER_SECS: u64 = 1;
+
 impl Handler for Orangutan {
-    type Timeout = ();
-    type Message = (Token, Vec<u8>);    
+    type Timeout = Timer;
+    type Message = Reply;    
 
     /// Handles events for the event loop.
-    /// Determines if the event is for the server socket (new connection) or an existing client (read/write).
+    /// Determines if the event is for a server socket (new connection) or an existing client (read/write).
+    /// Server sockets use the tokens before the first client token, one for each listen address.
     fn ready(&mut self, evl: &mut EventLoop<Orangutan>, token: Token, events: EventSet) {
-        if events.is_error() || events.is_hup() {
-            self.reset_connection(token);
+        let server = token.as_usize() < self.servers.len();
+
+        if !server && !self.conns.contains(token) {
             return;
         }
 
-        if events.is_readable() {
-            if self.token == token {
-                // New connection on the server socket
-                let sock = self.accept().unwrap();
-
-                if let Some(token) = self.conns.insert_with(|token| Client::new(sock, token)) {
-                    self.get_client(token).register(evl).ok();
-                }
-                self.reregister(evl);
+        if events.is_error() || (server && events.is_hup()) {
+            self.reset_connection(evl, token);
+            return;
+        }
 
+        if events.is_readable() || events.is_hup() {
+            if server {
+                // New connections on the server socket
+                self.accept(evl, token);
             } else {
-                // Read data from existing connection
-                self.readable(evl, token)
-                    .and_then(|_| self.get_client(token)
-                                      .reregister(evl)).ok();
+                // Read data from existing connection. A peer that hung up may only have closed its side,
+                // the requests it sent before that still get their responses.
+                match self.readable(evl, token, events.is_hup()) {
+                    Ok(_)   => { let _ = self.get_client(token).reregister(evl); },
+                    Err(_)  => self.reset_connection(evl, token),
+                }
             }
 
             return;
@@ -150,191 +244,675 @@ impl Handler for Orangutan {
 
         if events.is_writable() {
             match self.get_client(token).send() {
-                Ok(true)    => { self.reset_connection(token); },
-                Ok(false)   => { let _ = self.get_client(token).reregister(evl); },
-                Err(_)      => {},
+                Ok(true)    => self.flushed(evl, token),
+                Ok(false)   => {
+                    // the client took some of the response, so it gets a new write timeout
+                    let after = self.config.timeout(Phase::Write);
+                    let client = self.get_client(token);
+
+                    client.arm(evl, Phase::Write, after);
+                    let _ = client.reregister(evl);
+                },
+                Err(_)      => self.reset_connection(evl, token),
             }
         }
     }
 
-    fn notify(&mut self, evl: &mut EventLoop<Orangutan>, msg: (Token, Vec<u8>)) {
-        let (token, output) = msg;
-        let client = self.get_client(token);
+    fn notify(&mut self, evl: &mut EventLoop<Orangutan>, reply: Reply) {
+        let after = self.config.timeout(Phase::Write);
+
+        match self.conns.get_mut(reply.token) {
+            Some(client) if client.serial == reply.serial => {
+                client.o_buf.extend(reply.output);
+                client.keep_alive = reply.keep_alive;
+
+                if !reply.more {
+                    client.pull = None;
+                } else if reply.pull.is_some() {
+                    client.pull = reply.pull;
+                }
+
+                if client.o_buf.is_empty() {
+                    self.flushed(evl, reply.token);
+                } else {
+                    client.arm(evl, Phase::Write, after);
+                    let _ = client.reregister(evl);
+                }
+            },
+            _ => {}, // the client hung up while its handler was running
+        }
+    }
+
+    fn timeout(&mut self, evl: &mut EventLoop<Orangutan>, timer: Timer) {
+        match timer {
+            Timer::Client(token, serial, phase) => self.expire(evl, token, serial, phase),
+            Timer::Watch => {
+                if self.shutdown.is_triggered() {
+                    self.begin_shutdown(evl);
+                } else {
+                    let _ = evl.timeout_ms(Timer::Watch, WATCH_INTERVAL_MS);
+                }
+            },
+            Timer::Listen(token) => self.reregister(evl, token),
+            Timer::Deadline => evl.shutdown(),
+            Timer::Purge => {
+                // a store may have to look at every session, so it is done on a worker rather than the event loop
+                let sessions = Arc::clone(&self.sessions);
+                self.tpool.execute(move || sessions.purge());
+
+                let _ = evl.timeout_ms(Timer::Purge, session::PURGE_INTERVAL_SECS * 1000);
+            },
+        }
+    }
 
-        client.o_buf = output;
-        let _ = client.reregister(evl);
+    /// A signal interrupted the poll, it might have been a shutdown signal.
+    fn interrupted(&mut self, evl: &mut EventLoop<Orangutan>) {
+        if self.shutdown.is_triggered() {
+            self.begin_shutdown(evl);
+        }
+    }
+
+    /// Stops the event loop once the last connection of a shutdown has been closed.
+    fn tick(&mut self, evl: &mut EventLoop<Orangutan>) {
+        if self.draining && self.conns.is_empty() {
+            evl.shutdown();
+        }
     }
 }
 
 impl Orangutan {
-    pub fn new<A: ToSocketAddrs>(address: A) -> Self {  
+    /// Creates a new Orangutan listening on `address` with the default configuration. 
+    /// Every address the name resolves to is tried until one can be bound.
+    /// 
+    /// ```no_run
+    /// # use orangutan::Orangutan;
+    /// let mut app = Orangutan::new("127.0.0.1:8080")?;
+    /// # Ok::<(), orangutan::OrangutanError>(())
+    /// ```
+    pub fn new<A: ToSocketAddrs>(address: A) -> Result<Self, OrangutanError> {  
+        OrangutanBuilder::new().bind(address).build()
+    }
+
+    /// returns an OrangutanBuilder for configuring the server before it is created.
+    pub fn builder() -> OrangutanBuilder {
+        OrangutanBuilder::new()
+    }
 
-        let server = Some(TcpListener::bind(&address.to_socket_addrs().unwrap().next().unwrap()).unwrap());           
+    fn with_config(servers: Vec<TcpListener>, config: Config) -> Self {
+        // the first tokens belong to the server sockets
+        let first = Token(servers.len());
 
         Orangutan {
-            routes:  HashMap::new(), 
-            rcache:  HashMap::new(), 
-            server,
-            token:   Token(1),         
-            conns:   Slab::new_starting_at(Token(2), 2048),  
-            default: utils::err_404,
-            tpool:   ThreadPool::new(255),            
+            router:  route::Router::new(), 
+            servers,
+            conns:   Slab::new_starting_at(first, config.max_connections),  
+            serial:  0,
+            default: config.errors.get(&404).copied().unwrap_or(utils::err_404),
+            tpool:   ThreadPool::new(config.workers),            
+            sessions: Arc::new(config.sessions()),
+            config,
+            shutdown: ShutdownHandle::new(),
+            draining: false,
         }
     }
 
-    fn accept(&mut self) -> Result<TcpStream, std::io::Error> {
-        if let Some(ref server) = self.server {
-            if let Ok(s) = server.accept() {
-                if let Some((sock, _)) = s {
-                    return Ok(sock);
-                }
+    /// returns a handle that stops `run` gracefully from another thread or from a signal handler.
+    /// 
+    /// ```no_run
+    /// # use orangutan::Orangutan;
+    /// let mut app = Orangutan::new("127.0.0.1:8080")?;
+    ///
+    /// // CTRL+C and SIGTERM now let the running handlers finish before `run` returns
+    /// app.shutdown_handle().on_signals();
+    ///
+    /// app.run()?;
+    /// # Ok::<(), orangutan::OrangutanError>(())
+    /// ```
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts every connection waiting on the listener. The listener is edge-triggered, so whatever is left
+    /// in the backlog would otherwise only be picked up once another client connects.
+    fn accept(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) {
+        loop {
+            let accepted = match self.servers.get(token.as_usize()) {
+                Some(server) => server.accept(),
+                None         => return,
+            };
+
+            match accepted {
+                Ok(Some((sock, _))) => self.admit(evl, sock),
+                Ok(None)            => break,
+                Err(ref e) if out_of_descriptors(e) => {
+                    // the connections stay in the backlog until some descriptors have been freed
+                    eprintln!("  * unable to accept connections: {}", e);
+                    let _ = evl.timeout_ms(Timer::Listen(token), ACCEPT_BACKOFF_MS);
+                    return;
+                },
+                Err(ref e) if e.kind() == std::io::ErrorKind::ConnectionAborted
+                           || e.kind() == std::io::ErrorKind::ConnectionReset
+                           || e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_)              => break,
             }
         }
 
-        Err(std::io::Error::new(
-            std::io::ErrorKind::ConnectionAborted,
-            "connection aborted prematurely".to_string()
-        ))
+        self.reregister(evl, token);
     }
 
-    fn readable(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) -> Result<bool, std::io::Error> {
-        if let Ok(true) = self.get_client(token).receive() {
-            let buf = self.get_client(token).i_buf.clone();
-            if let Ok(rqstr) = String::from_utf8(buf) {
-                self.handle_request(token, evl.channel(), &rqstr);
-            } else {
-                return Ok(false);
+    /// Starts serving a new connection, or turns it away with 503 when every connection slot is taken.
+    fn admit(&mut self, evl: &mut EventLoop<Orangutan>, sock: TcpStream) {
+        if !self.conns.has_remaining() {
+            let mut response = self.error_response(503);
+            response.add_header("Retry-After", &RETRY_AFTER_SECS.to_string());
+            response.add_header("Connection", "close");
+
+            // best effort, a new socket has room for a short response and the client isn't waited for
+            let mut sock = sock;
+            let _ = sock.try_write(&response.to_bytes());
+            return;
+        }
+
+        self.serial += 1;
+        let serial = self.serial;
+        let capacity = self.config.read_buffer;
+
+        if let Some(token) = self.conns.insert_with(|token| Client::new(sock, token, serial, capacity)) {
+            let after = self.config.timeout(Phase::Head);
+            let client = self.get_client(token);
+
+            client.arm(evl, Phase::Head, after);
+
+            if client.register(evl).is_err() {
+                self.reset_connection(evl, token);
             }
         }
+    }
 
-        Ok(true)
+    /// Reads what the client sent and handles the request once all of it is there. Returns an Err once the connection
+    /// should be closed: the peer closed its side and there is no response left to send it.
+    fn readable(&mut self, evl: &mut EventLoop<Orangutan>, token: Token, hup: bool) -> Result<bool, std::io::Error> {
+        // one byte over the limits is enough for framing to tell that the request is too large
+        let limit = self.config.limits.max_request().saturating_add(1);
+        let size = self.config.read_buffer;
+        let client = self.get_client(token);
+
+        let read = client.receive(size, limit)?;
+        client.closed |= hup;
+
+        if read || client.closed {
+            self.process(evl, token);
+        }
+
+        let client = self.get_client(token);
+
+        if client.closed && !client.busy {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "connection closed by peer".to_string()
+            ));
+        }
+
+        Ok(read)
     }
 
-    fn reregister(&mut self, evl: &mut EventLoop<Orangutan>) {
-        if let Some(ref server) = self.server {
-            evl.reregister(server, self.token,
-                                 EventSet::readable(),
-                                 PollOpt::edge() | PollOpt::oneshot()).ok();
+    /// Handles the next request in the Client's input buffer once all of it has arrived. 
+    /// Requests that can never be valid are answered right away and the connection is closed.
+    fn process(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) {
+        let limits = self.config.limits;
+        let head_timeout = self.config.timeout(Phase::Head);
+        let body_timeout = self.config.timeout(Phase::Body);
+        let client = self.get_client(token);
+
+        if client.busy {
+            return;
+        }
+
+        let (head, body, trailers) = match framing::frame(&client.i_buf, &limits, &mut client.chunks) {
+            Ok(Frame::Head)                                     => {
+                // the head timer keeps running from the first byte, an idle connection starts it now
+                if client.phase != Some(Phase::Head) && !client.i_buf.is_empty() {
+                    client.arm(evl, Phase::Head, head_timeout);
+                }
+                return;
+            },
+            Ok(Frame::Body)                                     => {
+                client.arm(evl, Phase::Body, body_timeout);
+                return;
+            },
+            Ok(Frame::Complete { head, end })                   => {
+                let mut buf: Vec<u8> = client.i_buf.drain(..end).collect();
+                let body = buf.split_off(head);
+
+                (buf, body, Vec::new())
+            },
+            Ok(Frame::Chunked { head, end, body, trailers })    => {
+                client.chunks = Chunks::default();
+
+                let mut buf: Vec<u8> = client.i_buf.drain(..end).collect();
+                buf.truncate(head);
+
+                (buf, body, trailers)
+            },
+            Err(e)                                              => {
+                self.reject(evl, token, e.status());
+                return;
+            },
+        };
+
+        client.disarm(evl);
+
+        match Request::try_from(&head[..]) {
+            Ok(request) => self.handle_request(token, evl.channel(), request, body, trailers),
+            Err(e)      => self.reject(evl, token, e.status()),
         }
     }
 
-    pub fn run(&mut self) {        
-        let routes: std::sync::MutexGuard<Vec<RouteInfo>> = ROUTES.lock().unwrap();           
+    /// Answers the client with an error straight from the event loop and closes the connection once it is sent.
+    fn reject(&mut self, evl: &mut EventLoop<Orangutan>, token: Token, status: u16) {
+        let mut response = self.error_response(status);
+        response.add_header("Connection", "close");
 
-        let mut path = String::new();        
+        let after = self.config.timeout(Phase::Write);
+        let client = self.get_client(token);
 
-        let _a = path; // gets rid of the warning
-        
-        let mut handler: fn(&Request) -> Response = self.default;
+        client.i_buf.clear();
+        client.chunks = Chunks::default();
+        client.busy = true;
+        client.keep_alive = false;
+        client.o_buf = response.to_bytes();
+        client.arm(evl, Phase::Write, after);
+    }
 
-        let _b = handler; // gets rid of the warning
+    /// A connection spent too long in a phase. A client that got stuck halfway through a request is told so with 408,
+    /// everything else is simply closed.
+    fn expire(&mut self, evl: &mut EventLoop<Orangutan>, token: Token, serial: u64, phase: Phase) {
+        let client = match self.conns.get_mut(token) {
+            Some(client) if client.serial == serial && client.phase == Some(phase) => client,
+            _ => return,
+        };
 
-        let mut methods: HashSet<Method> = HashSet::new();        
+        client.timer = None;
 
-        for route in routes.iter() {                      
+        match phase {
+            Phase::Head if !client.i_buf.is_empty() => self.reject(evl, token, 408),
+            Phase::Body                             => self.reject(evl, token, 408),
+            _                                       => {
+                self.reset_connection(evl, token);
+                return;
+            },
+        }
+
+        if self.get_client(token).reregister(evl).is_err() {
+            self.reset_connection(evl, token);
+        }
+    }
+
+    fn reregister(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) {
+        if let Some(server) = self.servers.get(token.as_usize()) {
+            evl.reregister(server, token,
+                                 EventSet::readable(),
+                                 PollOpt::edge() | PollOpt::oneshot()).ok();
+        }
+    }
 
-            path = route.path.clone(); 
+    /// Registers the routes and serves requests until the server is shut down through its ShutdownHandle.
+    pub fn run(&mut self) -> Result<(), OrangutanError> {        
+        let routes: std::sync::MutexGuard<Vec<RouteInfo>> = ROUTES.lock().unwrap();           
+
+        let mut defined: Vec<route::Route> = Vec::new();
 
-            handler = route.handler; 
+        for route in routes.iter() {                      
+            let mut methods: Vec<Method> = Vec::new();
 
-            for method in route.methods.clone() {
-                methods.insert(match_method(&method));
+            for method in route.methods.iter().map(|method| match_method(method)) {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
             }
 
-            for m in &methods {                
-                let routedef = route::RouteDef {
-                    path: path.clone(),
-                    method: *m,
-                };
-    
-                if self.routes.contains_key(&routedef) {
-                    panic!("Route handler for {} has already been defined!", path); 
+            for m in methods {                
+                if defined.iter().any(|r| r.path == route.path && r.method == m) {
+                    return Err(OrangutanError::Route(format!("route handler for {} {} has already been defined", m, route.path))); 
                 }                
+
+                let handler = route::Route::new(&route.path, m, route.handler, &self.config.converters)
+                                           .map_err(|e| OrangutanError::Route(format!("invalid path {}: {}", route.path, e)))?;
     
-                self.routes.insert(routedef, route::Route::new(&path, *m, handler));
+                defined.push(handler);
             }
         }                               
 
-        let mut evl = match EventLoop::new() {
-            Ok(event_loop)  => event_loop,
-            Err(_)          => panic!("unable to initiate event loop"),
-        };
+        drop(routes);
 
-        match self.server {
-            None    => println!("server not bound to an address!"),
-            Some(_) => {                                                
-                let mut route_paths: Vec<String> = Vec::new();
+        // the sort is stable, so equally specific routes keep their registration order
+        defined.sort_by(|a, b| a.precedence(b));
+        report_overlaps(&defined);
 
-                for a in &self.routes {                      
-                    route_paths.push(a.0.path.clone());                                        
-                }                
+        for route in defined {
+            self.router.insert(route);
+        }
+
+        // every connection has at most one reply on its way and one timer running, so neither can run out
+        let mut evl_config = EventLoopConfig::new();
+        evl_config.notify_capacity(self.config.max_connections)
+                  .timer_capacity(self.config.max_connections + self.servers.len() + 2);
 
-                route_paths.sort_unstable();
-                route_paths.dedup(); 
+        let mut evl = EventLoop::configured(evl_config).map_err(OrangutanError::EventLoop)?;
 
-                println!("  * orangutan being served!");    
+        if self.servers.is_empty() {
+            return Err(OrangutanError::Bind(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "server not bound to an address".to_string()
+            )));
+        }
 
-                for route_path in route_paths.clone() {                                                        
-                    println!("    *  orangutan running on http://{:?}{} (Press CTRL+C to quit)", self.server.as_mut().unwrap().local_addr().unwrap(), route_path);       
-                }                    
+        let mut local_addrs = Vec::new();
 
-                self.register(&mut evl).ok();                
-                evl.run(self).unwrap();                         
-            },
-        };                 
+        for server in &self.servers {
+            local_addrs.push(server.local_addr().map_err(OrangutanError::Bind)?);
+        }
+
+        let mut route_paths: Vec<String> = Vec::new();
+
+        for a in self.router.routes() {                      
+            route_paths.push(a.path.clone());                                        
+        }                
+
+        route_paths.sort_unstable();
+        route_paths.dedup(); 
+
+        println!("  * orangutan being served!");    
+
+        for local_addr in &local_addrs {
+            for route_path in &route_paths {                                                        
+                println!("    *  orangutan running on http://{:?}{} (Press CTRL+C to quit)", local_addr, route_path);       
+            }                    
+        }
+
+        self.register(&mut evl).map_err(OrangutanError::EventLoop)?;                
+        let _ = evl.timeout_ms(Timer::Watch, WATCH_INTERVAL_MS);
+
+        if self.sessions.purges() {
+            let _ = evl.timeout_ms(Timer::Purge, session::PURGE_INTERVAL_SECS * 1000);
+        }
+
+        evl.run(self).map_err(OrangutanError::EventLoop)?;                         
+
+        println!("  * orangutan stopped");
+
+        Ok(())
     }
 
     fn register(&mut self, evl: &mut EventLoop<Orangutan>) -> Result<(), std::io::Error> {
-        if let Some(ref server) = self.server {            
-            return evl.register(server, self.token, EventSet::readable(), PollOpt::edge() | PollOpt::oneshot());
+        for (i, server) in self.servers.iter().enumerate() {            
+            evl.register(server, Token(i), EventSet::readable(), PollOpt::edge() | PollOpt::oneshot())?;
         }
 
         Ok(())
     }
 
-    fn reset_connection(&mut self, token: Token) {     
-        self.conns.remove(token);
+    /// Stops accepting connections and closes the idle ones. Busy connections are closed as soon as
+    /// their response has been sent, and the event loop stops once none are left or the shutdown timeout passes.
+    fn begin_shutdown(&mut self, evl: &mut EventLoop<Orangutan>) {
+        if self.draining {
+            return;
+        }
+
+        self.draining = true;
+
+        for server in self.servers.drain(..) {
+            let _ = evl.deregister(&server);
+        }
+
+        let idle: Vec<Token> = self.conns.iter()
+                                         .filter(|client| !client.busy && client.o_buf.is_empty())
+                                         .map(|client| client.token)
+                                         .collect();
+
+        for token in idle {
+            self.reset_connection(evl, token);
+        }
+
+        let _ = evl.timeout_ms(Timer::Deadline, self.config.shutdown.as_millis() as u64);
     }
 
-    fn handle_request(&mut self, token: Token, tx: Sender<(Token, Vec<u8>)>, rqstr: &str) {   
-        let mut request = match rqstr.parse::<Request>() {
-            Ok(req) => req,
-            Err(e) => {
-                eprintln!("Failed to parse request: {:?}", e);
-                return;
-            }
-        };
+    fn reset_connection(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) {     
+        if let Some(mut client) = self.conns.remove(token) {
+            client.disarm(evl);
+        }
+    }
 
-        let mut handler: fn(&Request) -> Response = self.default;        
+    /// Called once everything in the output buffer has been written. A streamed response asks its worker 
+    /// for the next piece, anything else is done.
+    fn flushed(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) {
+        let client = self.get_client(token);
+
+        match client.pull {
+            Some(ref pull) => {
+                if pull.send(()).is_err() {
+                    self.reset_connection(evl, token);
+                } else {
+                    // the worker is producing the next piece, which isn't limited
+                    client.disarm(evl);
+                    let _ = client.reregister(evl);
+                }
+            },
+            None => self.finish_response(evl, token),
+        }
+    }
+
+    /// Called once a response has been fully written. Either closes the connection or 
+    /// gets the Client ready for the next request on the same connection.
+    fn finish_response(&mut self, evl: &mut EventLoop<Orangutan>, token: Token) {
+        let idle_timeout = self.config.timeout(Phase::Idle);
+        let draining = self.draining;
+        let client = self.get_client(token);
+
+        client.busy = false;
+
+        if !client.keep_alive || draining {
+            self.reset_connection(evl, token);
+            return;
+        }
+
+        client.arm(evl, Phase::Idle, idle_timeout);
 
-        let routedef = route::RouteDef {
-            path: request.path.clone(),
-            method:  request.method,
-        };        
+        // a pipelined request might already be waiting in the input buffer
+        self.process(evl, token);
 
-        if self.rcache.contains_key(&routedef) {
-            let route = &self.routes[&self.rcache[&routedef]];
+        let client = self.get_client(token);
+
+        // nothing more is coming from a peer that closed its side
+        if client.closed && !client.busy {
+            self.reset_connection(evl, token);
+            return;
+        }
+
+        if client.reregister(evl).is_err() {
+            self.reset_connection(evl, token);
+        }
+    }
 
+    fn handle_request(&mut self, token: Token, tx: Sender<Reply>, mut request: Request, body: Vec<u8>, trailers: Vec<(String, String)>) {   
+        request.body = body;
+
+        request.trailers = trailers.into_iter().collect();
+
+        let max_requests = self.config.max_requests;
+        let client = self.get_client(token);
+
+        client.busy = true;
+        client.served += 1;
+
+        let serial = client.serial;
+        let keep_alive = request.keep_alive() && client.served < max_requests;
+
+        let mut handler: fn(&Request) -> Response = self.default;        
+        let mut pattern: Option<String> = None;
+        let on_panic = self.error_handler(500);
+        let on_missing_var = self.error_handler(404);
+        let on_invalid_var = self.error_handler(400);
+
+        // HEAD is answered by the GET handler unless the route has its own
+        let found = match self.router.find(&request.path, &request.method) {
+            None if request.method == Method::HEAD => self.router.find(&request.path, &Method::GET),
+            found                                  => found,
+        };
+
+        // set when the path exists but not for this method
+        let mut allow: Option<String> = None;
+
+        if let Some((route, params)) = found {
             handler = route.handler;
-            request.params = route.parse(&request.path);
-        } else {            
-            for (path, route) in &self.routes {                
-
-                if route.is_match(&request) {                
-                    handler = route.handler;
-                    request.params = route.parse(&request.path);
-                    self.rcache.insert(routedef.clone(), (*path).clone());
-                    break;
-                }
-            }
-        }        
+            request.params = params;
+            pattern = Some(route.path.clone());
+        } else if let Some(methods) = self.allowed_methods(&request) {
+            handler = match request.method {
+                Method::OPTIONS => answer_options,
+                _               => self.error_handler(405),
+            };
+            allow = Some(methods);
+        }
+
+        let sessions = Arc::clone(&self.sessions);
 
         self.tpool.execute(move || {
-            let _ = tx.send((token, handler(&request).to_bytes()));
+            let loaded = sessions.load(&mut request);
+
+            // a panicking handler still has to answer, or the client would wait for a response that never comes
+            let response = match panic::catch_unwind(AssertUnwindSafe(|| handler(&request))) {
+                Ok(mut response) => {
+                    sessions.save(&request, &mut response, loaded);
+                    response
+                },
+                Err(payload) => {
+                    // get_var stops the handler with a ParamError when the route variable is missing or doesn't fit its type
+                    let answer = match payload.downcast_ref::<ParamError>() {
+                        Some(ParamError::Missing(_))     => on_missing_var,
+                        Some(ParamError::Invalid { .. }) => on_invalid_var,
+                        None                             => on_panic,
+                    };
+
+                    if cfg!(debug_assertions) {
+                        let stopped = if payload.is::<ParamError>() { "stopped" } else { "panicked" };
+
+                        eprintln!("  * handler for {} {} on {} {}: {}",
+                                  pattern.as_deref().unwrap_or("<default>"), stopped, request.method, request.path, panic_message(&*payload));
+                    }
+
+                    panic::catch_unwind(AssertUnwindSafe(|| answer(&request))).unwrap_or_else(|_| utils::err_500(&request))
+                },
+            };
+
+            let mut response = response;
+
+            if let Some(allow) = allow {
+                response.add_header("Allow", &allow);
+            }
+
+            reply::send(&tx, token, serial, &request, response, keep_alive);
         });        
     }
 
+    /// returns the methods the path can be requested with, for the Allow header, or None if no route matches the path.
+    /// `OPTIONS *` asks about the whole server.
+    fn allowed_methods(&self, request: &Request) -> Option<String> {
+        let whole_server = request.path == "*" && request.method == Method::OPTIONS;
+
+        let routes: Vec<&route::Route> = if whole_server {
+            self.router.routes().iter().collect()
+        } else {
+            self.router.matching(&request.path)
+        };
+
+        let mut methods: Vec<&str> = routes.iter().map(|route| route.method.as_str()).collect();
+
+        if methods.is_empty() {
+            return None;
+        }
+
+        if methods.contains(&"GET") {
+            methods.push("HEAD");
+        }
+
+        methods.push("OPTIONS");
+        methods.sort_unstable();
+        methods.dedup();
+
+        Some(methods.join(", "))
+    }
+
     fn get_client(&mut self, token: Token) -> &mut Client {        
         self.conns.get_mut(token).unwrap()
     }
-}
\ No newline at end of file
+
+    /// The handler that answers with `status`: the one set with `error_handler`, or the built-in page.
+    fn error_handler(&self, status: u16) -> fn(&Request) -> Response {
+        let builtin = match status {
+            400 => utils::err_400,
+            404 => utils::err_404,
+            405 => utils::err_405,
+            _   => utils::err_500,
+        };
+
+        self.config.errors.get(&status).copied().unwrap_or(builtin)
+    }
+
+    /// The response for an error the event loop answers by itself, from the configured error handler if there is one.
+    fn error_response(&self, status: u16) -> Response {
+        let request = Request::new();
+
+        let configured = self.config.errors.get(&status)
+                                           .and_then(|handler| panic::catch_unwind(AssertUnwindSafe(|| handler(&request))).ok());
+
+        configured.unwrap_or_else(|| {
+            let mut response = Response::new();
+            response.abort(&request, status);
+            response
+        })
+    }
+}
+
+/// Tells which route wins for every two routes of the same method that can match the same path and are just as specific,
+/// so only the order they were defined in decides. When one is more specific the precedence rules already say which wins.
+fn report_overlaps(routes: &[route::Route]) {
+    for (i, first) in routes.iter().enumerate() {
+        for second in &routes[i + 1..] {
+            let ambiguous = first.precedence(second) == std::cmp::Ordering::Equal;
+
+            if first.method == second.method && ambiguous && first.overlaps(second) {
+                println!("  * {} {} overlaps with {}, {} is tried first", first.method, first.path, second.path, first.path);
+            }
+        }
+    }
+}
+
+/// Answers OPTIONS for paths whose routes don't handle it themselves. The Allow header is added by the caller.
+fn answer_options(_request: &Request) -> Response {
+    Response::new()
+}
+
+/// The message a handler panicked with, when it is a string or a ParamError.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(error) = payload.downcast_ref::<ParamError>() {
+        error.to_string()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+/// Tells if accepting failed because the process or the system has no file descriptors left.
+#[cfg(unix)]
+fn out_of_descriptors(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+}
+
+#[cfg(not(unix))]
+fn out_of_descriptors(_e: &std::io::Error) -> bool {
+    false
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use lib_shared::request::{Method, Request, Version};
+use lib_shared::response::Response;
+
+use mio::{NotifyError, Sender, Token};
+
+/// Output on its way from a worker thread back to the event loop: a whole response,
+/// or one piece of a streamed one. The serial makes sure late output never ends up on a connection that reused the token.
+pub struct Reply {
+    pub token:      Token,
+    pub serial:     u64,
+    pub output:     Vec<u8>,
+    pub keep_alive: bool,
+    /// True while a streamed body still has pieces to come.
+    pub more:       bool,
+    /// Sent along with the head of a streamed response. The event loop asks for the next piece through it
+    /// once the client has received everything sent so far, and drops it when the client goes away.
+    pub pull:       Option<mpsc::Sender<()>>,
+}
+
+/// Sends the response of a handler back to the event loop. Runs on the worker thread.
+///
+/// A streamed body keeps the worker busy until the whole body has been written,
+/// reading the next piece only when the event loop asks for it.
+///
+/// A HEAD request gets the same head a GET would, without the body.
+pub fn send(tx: &Sender<Reply>, token: Token, serial: u64, request: &Request, mut response: Response, keep_alive: bool) {
+    let keep_alive = keep_alive && !response.closes_connection();
+    let head_only = request.method == Method::HEAD;
+
+    if response.stream.is_none() {
+        response.add_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+        let output = if head_only { response.head_bytes() } else { response.to_bytes() };
+        deliver(tx, Reply { token, serial, output, keep_alive, more: false, pull: None });
+        return;
+    }
+
+    // without a length or chunked encoding the body can only end by closing the connection
+    let sized = response.headers.contains_key("Content-Length");
+    let chunked = !sized && request.version == Version::HTTP11;
+    let keep_alive = keep_alive && (sized || chunked);
+
+    if chunked {
+        response.add_header("Transfer-Encoding", "chunked");
+    }
+    response.add_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+    let head = response.to_bytes();
+
+    if head_only {
+        deliver(tx, Reply { token, serial, output: head, keep_alive, more: false, pull: None });
+        return;
+    }
+
+    let mut stream = match response.stream.take() {
+        Some(stream) => stream,
+        None         => return,
+    };
+
+    let (pull, pulled) = mpsc::channel::<()>();
+
+    if !deliver(tx, Reply { token, serial, output: head, keep_alive, more: true, pull: Some(pull) }) {
+        return;
+    }
+
+    loop {
+        // a panicking stream is treated like one that failed to read
+        let chunk = panic::catch_unwind(AssertUnwindSafe(|| stream.next_chunk()))
+                          .unwrap_or_else(|_| Some(Err(io::Error::other("the stream panicked"))));
+
+        // wait until the client has taken everything sent so far. An error means the client is gone
+        if pulled.recv().is_err() {
+            return;
+        }
+
+        let reply = match chunk {
+            Some(Ok(data)) => {
+                let output = if chunked { frame_chunk(&data) } else { data };
+                Reply { token, serial, output, keep_alive, more: true, pull: None }
+            },
+            Some(Err(_))   => {
+                // the status has already been sent, the only way to tell the client is to cut the body short
+                Reply { token, serial, output: Vec::new(), keep_alive: false, more: false, pull: None }
+            },
+            None           => {
+                let output = if chunked { b"0\r\n\r\n".to_vec() } else { Vec::new() };
+                Reply { token, serial, output, keep_alive, more: false, pull: None }
+            },
+        };
+
+        let more = reply.more;
+
+        if !deliver(tx, reply) || !more {
+            return;
+        }
+    }
+}
+
+/// Hands a reply to the event loop. returns false if it couldn't be, because the event loop has stopped.
+///
+/// The queue is sized for a reply from every connection, but should it still be full the worker waits for the event loop
+/// to take some out. Dropping the reply would leave the client waiting forever, a busy connection has no timer.
+fn deliver(tx: &Sender<Reply>, mut reply: Reply) -> bool {
+    loop {
+        match tx.send(reply) {
+            Ok(())                        => return true,
+            Err(NotifyError::Full(back))  => {
+                reply = back;
+                thread::sleep(Duration::from_millis(1));
+            },
+            Err(NotifyError::Closed(_))   => return false,
+            Err(NotifyError::Io(e))       => {
+                eprintln!("  * unable to hand a response to the event loop: {}", e);
+                return false;
+            },
+        }
+    }
+}
+
+/// Wraps a piece of the body into a chunk: the size in hex, the data and a CRLF.
+fn frame_chunk(data: &[u8]) -> Vec<u8> {
+    let mut chunk = format!("{:X}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
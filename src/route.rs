@@ -1,106 +1,458 @@
-use std::collections::HashMap;
-use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
 use lib_shared::request::{Method, Request};
 
 use lib_shared::response::Response;
 
-#[derive(PartialEq, Eq, Hash, Debug)]
-pub enum ParamType { 
-    Integer,
-    Unsigned,
-    String,
-    Float,
-    Path,
+use crate::converter::{Converter, Converters};
+
+/// A `<converter:name>` segment of a route path.
+#[derive(Clone)]
+pub struct Param {
+    pub name:      String,
+    /// The name the converter was registered with.
+    pub converter: String,
+    /// What was given in parentheses after the converter name, split on commas.
+    pub args:      Vec<String>,
+    imp:           Arc<dyn Converter>,
+}
+
+impl Param {
+    /// true if `value` is a valid value of the parameter. Unless the converter spans segments, `value` is a single segment.
+    pub fn accepts(&self, value: &str) -> bool {
+        self.imp.matches(value, &self.args)
+    }
+
+    /// The value the handler gets for a valid `value`.
+    pub fn convert(&self, value: &str) -> String {
+        self.imp.convert(value, &self.args)
+    }
+
+    pub fn spans_segments(&self) -> bool {
+        self.imp.spans_segments()
+    }
+
+    fn rank(&self) -> u8 {
+        self.imp.rank()
+    }
+}
+
+impl PartialEq for Param {
+    fn eq(&self, other: &Param) -> bool {
+        self.name == other.name && self.converter == other.converter && self.args == other.args
+    }
+}
+
+impl Eq for Param {}
+
+impl fmt::Debug for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Param")
+         .field("name", &self.name)
+         .field("converter", &self.converter)
+         .field("args", &self.args)
+         .finish()
+    }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
-pub struct RouteDef {
-    pub path: String,
-    pub method:  Method,
+/// One segment of a route path, between two slashes.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Segment {
+    Static(String),
+    Param(Param),
+}
+
+impl Segment {
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_)    => 0,
+            Segment::Param(param) => param.rank(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Route {
-    matcher:     Regex, 
+    pub path:        String,
     pub method:      Method,
-    pub params:      HashMap<String, ParamType>,
+    pub segments:    Vec<Segment>,
     pub handler: fn(&Request) -> Response,
 }
 
 
 impl Route {
+    /// Splits the path into its segments, looking up the converter of every parameter in `converters`.
+    /// A parameter without a converter is a str. Fails if a converter doesn't exist, two parameters have the same name
+    /// or more than one parameter spans segments, which would make a path with many segments slow to match.
     pub fn new(path: &str, method: Method, 
-        handler: fn(&Request) -> Response) -> Self { 
+        handler: fn(&Request) -> Response, converters: &Converters) -> Result<Self, String> { 
 
-        let re = Regex::new(r"^<(?:(int|uint|str|float|path):)?([\w_][a-zA-Z0-9_]*)>$").unwrap();
+        let parts: Vec<&str> = path.split('/').filter(|&s| !s.is_empty()).collect();        
 
-        let parts: Vec<&str> = path.split('/').filter(|&s| s != "").collect();        
+        let mut names: HashSet<String> = HashSet::new();
+        let mut segments: Vec<Segment> = Vec::new();
 
-        let mut matcher: String = String::from(r"^");
+        for part in parts {
+            match lib_shared::placeholder(part) {
+                Some(placeholder) => {
+                    let imp = converters.get(&placeholder.converter)
+                                        .ok_or_else(|| format!("there is no converter called {}", placeholder.converter))?;
 
-        let mut params: HashMap<String, ParamType> = HashMap::new();
+                    if !names.insert(placeholder.name.clone()) {
+                        return Err(format!("parameter {} is used more than once", placeholder.name));
+                    }
 
-        for part in parts {
-            let chunk: String = if re.is_match(part) {
-                let caps = re.captures(part).unwrap();
-                let param = caps.get(2).unwrap().as_str();
-                let ptype: ParamType = match caps.get(1) {
-                    Some(x)     => {
-                        match x.as_str() {
-                            "int"   => ParamType::Integer,
-                            "uint"  => ParamType::Unsigned,
-                            "float" => ParamType::Float,
-                            "path"  => ParamType::Path,
-                            "str"   => ParamType::String,
-                            _       => ParamType::String,
-                        }
+                    if imp.spans_segments() && segments.iter().any(|s| matches!(s, Segment::Param(p) if p.spans_segments())) {
+                        return Err(format!("parameter {} spans segments like an earlier one, a route can only have one", placeholder.name));
                     }
-                    None        => ParamType::String,
-                };
-
-                let mstr: String = match ptype {
-                    ParamType::String   => String::from(r"(?:[^/])+"),
-                    ParamType::Integer  => String::from(r"-*[0-9]+"),
-                    ParamType::Unsigned => String::from(r"[0-9]+"),
-                    ParamType::Float    => String::from(r"-*[0-9]*[.]?[0-9]+"),
-                    ParamType::Path     => String::from(r".+"),
-                };
-
-                params.insert(String::from(param), ptype);
-
-                format!("/(?P<{}>{})", &param, &mstr)
-            } else {
-                String::from("/") + part
-            };
 
-            matcher.push_str(&chunk);
+                    segments.push(Segment::Param(Param {
+                        name:      placeholder.name,
+                        converter: placeholder.converter,
+                        args:      placeholder.args,
+                        imp,
+                    }));
+                },
+                None              => segments.push(Segment::Static(String::from(part))),
+            }
         }
 
-        matcher.push_str("/?$");                   
-
-        Route {
-            matcher: Regex::new(&matcher).unwrap(),
-            params,
+        Ok(Route {
+            path: String::from(path),
+            segments,
             method,
             handler,
-        }        
+        })
+    }
+
+    /// Orders routes by which one gets a path that both of them match. Segments are compared from the left:
+    /// static segments win over int, uint, float and most other converters, which win over str, which wins over path.
+    /// Routes that are just as specific are Equal and keep their registration order.
+    pub fn precedence(&self, other: &Route) -> Ordering {
+        let ranks = |route: &Route| route.segments.iter().map(Segment::rank).collect::<Vec<u8>>();
+
+        ranks(self).cmp(&ranks(other))
+    }
+
+    /// true if some path is matched by both routes, whatever their methods.
+    pub fn overlaps(&self, other: &Route) -> bool {
+        overlap(&self.segments, &other.segments)
+    }
+}
+
+/// true if a path can be matched by both lists of segments. A path parameter takes up one or more segments.
+fn overlap(a: &[Segment], b: &[Segment]) -> bool {
+    let is_path = |segment: &Segment| matches!(segment, Segment::Param(param) if param.spans_segments());
+
+    match (a.first(), b.first()) {
+        (None, None)                     => true,
+        (Some(x), Some(y)) if is_path(x) => {
+            // the path parameter ends here, takes the next segment too, or outlasts a path parameter on the other side
+            overlap(&a[1..], &b[1..]) || overlap(a, &b[1..]) || (is_path(y) && overlap(&a[1..], b))
+        },
+        (Some(_), Some(y)) if is_path(y) => overlap(b, a),
+        (Some(x), Some(y))               => compatible(x, y) && overlap(&a[1..], &b[1..]),
+        _                                => false,
     }
+}
+
+/// true if a single path segment can be matched by both.
+fn compatible(a: &Segment, b: &Segment) -> bool {
+    match (a, b) {
+        (Segment::Static(x), Segment::Static(y))       => x == y,
+        (Segment::Static(x), Segment::Param(param))    => param.accepts(x),
+        (Segment::Param(param), Segment::Static(y))    => param.accepts(y),
+        // converters can't be asked which values they share, so any two are assumed to overlap
+        (Segment::Param(..), Segment::Param(..))       => true,
+    }
+}
+
+/// Finds the route for a path by walking down a tree with a branch for every segment of the registered paths.
+///
+/// At every segment the static branch is tried first, then the typed parameters, then str and then path,
+/// backing up when a branch leads nowhere. That gives the same precedence as `Route::precedence`
+/// without running a regex or remembering the paths that have been requested.
+#[derive(Debug, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    root:   Node,
+}
 
-    pub fn parse(&self, path: &str) -> HashMap<String, String> {
-        let mut params: HashMap<String, String> = HashMap::new();
+/// The parameters on the way down the tree and the part of the path each one matched.
+type Params<'a> = Vec<(&'a Param, &'a str)>;
 
-        if self.matcher.is_match(&path) {
-            let caps = self.matcher.captures(path).unwrap();
-            for param in self.params.keys() {
-                params.insert(param.clone(), String::from(caps.name(&param).unwrap().as_str()));
+#[derive(Debug, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    /// Kept sorted by rank, branches of the same rank in the order they were added.
+    params:  Vec<(Param, Node)>,
+    /// The routes whose path ends here, one for each method.
+    routes:  Vec<usize>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Adds a route. Routes added earlier win over equally specific ones added later.
+    pub fn insert(&mut self, route: Route) {
+        let mut node = &mut self.root;
+
+        for segment in &route.segments {
+            node = match segment {
+                Segment::Static(part)        => node.statics.entry(part.clone()).or_default(),
+                Segment::Param(param)        => {
+                    let position = match node.params.iter().position(|(p, _)| p == param) {
+                        Some(position) => position,
+                        None           => {
+                            let position = node.params.iter()
+                                                      .position(|(p, _)| p.rank() > param.rank())
+                                                      .unwrap_or(node.params.len());
+
+                            node.params.insert(position, (param.clone(), Node::default()));
+                            position
+                        },
+                    };
+
+                    &mut node.params[position].1
+                },
+            };
+        }
+
+        node.routes.push(self.routes.len());
+        self.routes.push(route);
+    }
+
+    /// returns every route, in the order they were added.
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// returns the route that handles `method` on `path` and the values of its parameters.
+    pub fn find(&self, path: &str, method: &Method) -> Option<(&Route, HashMap<String, String>)> {
+        let mut found = None;
+
+        Walk::new(path)?.run(&self.root, &mut |node, params| {
+            match node.routes.iter().map(|&i| &self.routes[i]).find(|route| route.method == *method) {
+                Some(route) => {
+                    let params = params.iter().map(|(param, value)| (param.name.clone(), param.convert(value))).collect();
+                    found = Some((route, params));
+                    true
+                },
+                None        => false,
             }
+        });
+
+        found
+    }
+
+    /// returns every route that matches `path`, whatever its method.
+    pub fn matching(&self, path: &str) -> Vec<&Route> {
+        let mut matching = Vec::new();
+
+        if let Some(walk) = Walk::new(path) {
+            walk.run(&self.root, &mut |node, _| {
+                matching.extend(node.routes.iter().map(|&i| &self.routes[i]));
+                false
+            });
         }
 
-        params
+        matching
     }
+}
+
+/// One lookup of a request path in the tree.
+///
+/// The values of the parameters are slices of the path and only converted for the route that is found,
+/// so trying every way a path parameter can end costs no more than the walk below it.
+/// A route has at most one path parameter, which keeps the number of ways linear in the number of segments.
+struct Walk<'p> {
+    /// The request path without its leading and trailing slash.
+    path:     &'p str,
+    /// Where every segment starts and ends in `path`.
+    segments: Vec<(usize, usize)>,
+}
+
+impl<'p> Walk<'p> {
+    /// Splits a request path into its segments. A trailing slash is ignored, and paths that don't start with one match nothing.
+    fn new(path: &'p str) -> Option<Walk<'p>> {
+        let path = path.strip_prefix('/')?;
+        let path = path.strip_suffix('/').unwrap_or(path);
+
+        let mut segments = Vec::new();
+        let mut start = 0;
 
-    pub fn is_match(&self, req: &Request) -> bool {
-        self.matcher.is_match(&req.path) && self.method == req.method
+        if !path.is_empty() {
+            for end in path.match_indices('/').map(|(i, _)| i).chain(std::iter::once(path.len())) {
+                segments.push((start, end));
+                start = end + 1;
+            }
+        }
+
+        Some(Walk { path, segments })
+    }
+
+    /// Visits the nodes that the path leads to, most specific first, until `visit` returns true.
+    fn run<'t>(&self, root: &'t Node, visit: &mut dyn FnMut(&'t Node, &Params<'p>) -> bool) -> bool
+    where 't: 'p {
+        self.walk(root, 0, &mut Vec::new(), visit)
+    }
+
+    fn walk<'t>(&self, node: &'t Node, index: usize, params: &mut Params<'p>, visit: &mut dyn FnMut(&'t Node, &Params<'p>) -> bool) -> bool
+    where 't: 'p {
+        let (start, end) = match self.segments.get(index) {
+            Some(&segment) => segment,
+            None           => return visit(node, params),
+        };
+
+        if let Some(child) = node.statics.get(&self.path[start..end]) {
+            if self.walk(child, index + 1, params, visit) {
+                return true;
+            }
+        }
+
+        for (param, child) in &node.params {
+            // a path parameter takes as many segments as it can, like the greedy regex it replaces
+            let last = if param.spans_segments() { self.segments.len() } else { index + 1 };
+
+            for next in (index + 1..=last).rev() {
+                let value = &self.path[start..self.segments[next - 1].1];
+
+                if !param.accepts(value) {
+                    continue;
+                }
+
+                params.push((param, value));
+
+                if self.walk(child, next, params, visit) {
+                    return true;
+                }
+
+                params.pop();
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn handler(_request: &Request) -> Response {
+        Response::new()
+    }
+
+    fn router(paths: &[&str], converters: &Converters) -> Router {
+        let mut router = Router::new();
+
+        for path in paths {
+            router.insert(Route::new(path, Method::GET, handler, converters).unwrap());
+        }
+
+        router
+    }
+
+    /// returns the path of the route that gets `path`, and its parameters sorted by name.
+    fn find(router: &Router, path: &str) -> Option<(String, Vec<(String, String)>)> {
+        let (route, params) = router.find(path, &Method::GET)?;
+        let mut params: Vec<(String, String)> = params.into_iter().collect();
+        params.sort();
+
+        Some((route.path.clone(), params))
+    }
+
+    fn found(path: &str, params: &[(&str, &str)]) -> Option<(String, Vec<(String, String)>)> {
+        Some((path.to_string(), params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn static_wins_over_typed_over_str_over_path() {
+        // registered from the least to the most specific, so the order doesn't decide
+        let router = router(&["/files/<path:rest>", "/files/<str:name>", "/files/<int:id>", "/files/latest"], &Converters::default());
+
+        assert_eq!(find(&router, "/files/latest"), found("/files/latest", &[]));
+        assert_eq!(find(&router, "/files/-7"), found("/files/<int:id>", &[("id", "-7")]));
+        assert_eq!(find(&router, "/files/notes.txt"), found("/files/<str:name>", &[("name", "notes.txt")]));
+        assert_eq!(find(&router, "/files/a/b/c"), found("/files/<path:rest>", &[("rest", "a/b/c")]));
+    }
+
+    #[test]
+    fn equally_specific_routes_keep_their_order() {
+        let router = router(&["/<str:first>", "/<str:second>"], &Converters::default());
+
+        assert_eq!(find(&router, "/hello"), found("/<str:first>", &[("first", "hello")]));
+    }
+
+    #[test]
+    fn trailing_slash_and_missing_leading_slash() {
+        let router = router(&["/", "/about"], &Converters::default());
+
+        assert_eq!(find(&router, "/"), found("/", &[]));
+        assert_eq!(find(&router, "/about/"), found("/about", &[]));
+        assert_eq!(find(&router, "about"), None);
+    }
+
+    #[test]
+    fn backs_up_when_a_branch_leads_nowhere() {
+        let router = router(&["/users/<int:id>/posts", "/users/<str:name>/profile"], &Converters::default());
+
+        assert_eq!(find(&router, "/users/42/profile"), found("/users/<str:name>/profile", &[("name", "42")]));
+        assert_eq!(find(&router, "/users/42/posts"), found("/users/<int:id>/posts", &[("id", "42")]));
+    }
+
+    #[test]
+    fn path_takes_as_many_segments_as_it_can() {
+        let router = router(&["/<path:dir>/<str:file>", "/files/<path:file>/raw"], &Converters::default());
+
+        assert_eq!(find(&router, "/a/b/c"), found("/<path:dir>/<str:file>", &[("dir", "a/b"), ("file", "c")]));
+        assert_eq!(find(&router, "/files/a/raw/b/raw"), found("/files/<path:file>/raw", &[("file", "a/raw/b")]));
+    }
+
+    #[test]
+    fn only_one_parameter_can_span_segments() {
+        let converters = Converters::default();
+
+        assert!(Route::new("/<path:a>/<path:b>/end", Method::GET, handler, &converters).is_err());
+        assert!(Route::new("/<path:a>/<str:b>/<int:c>", Method::GET, handler, &converters).is_ok());
+    }
+
+    /// A path converter that counts how often it is asked.
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Converter for Counted {
+        fn matches(&self, value: &str, _args: &[String]) -> bool {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            !value.is_empty()
+        }
+
+        fn rank(&self) -> u8 {
+            3
+        }
+
+        fn spans_segments(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn backtracking_over_path_parameters_is_bounded() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut converters = Converters::default();
+        converters.insert("counted", Counted(calls.clone()));
+
+        let router = router(&["/<counted:a>/<str:b>/<int:c>/end", "/x/<counted:d>/end"], &converters);
+
+        let segments = 2000;
+        let path = "/x".repeat(segments);
+
+        assert_eq!(find(&router, &path), None);
+        assert!(calls.load(Ordering::Relaxed) <= 2 * segments, "{} calls", calls.load(Ordering::Relaxed));
+    }
+}
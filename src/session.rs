@@ -0,0 +1,764 @@
+//! Where `request.session` is kept between requests: in a signed cookie, or in a SessionStore on the server.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lib_shared::cookie::{Cookie, SameSite};
+use lib_shared::request::Request;
+use lib_shared::response::Response;
+use lib_shared::session::Session;
+use serde_json::{json, Map, Value};
+
+use crate::signing::{base64url_decode, base64url_encode, hmac_sha256, verify_hmac_sha256};
+
+/// Browsers drop cookies larger than this, name and attributes included.
+const MAX_COOKIE_SIZE: usize = 4093;
+
+/// How often expired sessions are removed from a SessionStore.
+pub(crate) const PURGE_INTERVAL_SECS: u64 = 60;
+
+/// A session as a SessionStore keeps it. Times are seconds since 1970.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub values:    Map<String, Value>,
+    /// When the session was created. Sessions older than the session lifetime expire.
+    pub created:   u64,
+    /// When the client last used the session. Sessions unused for the idle timeout expire.
+    pub last_seen: u64,
+}
+
+/// Keeps sessions on the server, with only a random id in the session cookie. Set with `OrangutanBuilder::session_store`.
+///
+/// Stores are used from every worker at once. The server decides when sessions expire and which ids are new,
+/// a store only keeps what it is given:
+///
+/// ```no_run
+/// # use orangutan::session::{SessionRecord, SessionStore};
+/// # use std::collections::HashMap;
+/// # use std::io;
+/// # use std::sync::Mutex;
+/// struct Logged(Mutex<HashMap<String, SessionRecord>>);
+///
+/// impl SessionStore for Logged {
+///     fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+///         Ok(self.0.lock().unwrap().get(id).cloned())
+///     }
+///
+///     fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+///         println!("session saved with {} values", record.values.len());
+///         self.0.lock().unwrap().insert(id.to_string(), record.clone());
+///         Ok(())
+///     }
+///
+///     fn remove(&self, id: &str) -> io::Result<()> {
+///         self.0.lock().unwrap().remove(id);
+///         Ok(())
+///     }
+///
+///     fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+///         self.0.lock().unwrap().retain(|_, record| !expired(record));
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait SessionStore: Send + Sync {
+    /// returns the session with the id, or None if there is none.
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>>;
+
+    /// Stores the session under the id, replacing what was there.
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()>;
+
+    /// Removes the session with the id, if there is one.
+    fn remove(&self, id: &str) -> io::Result<()>;
+
+    /// Sets when the session with the id was last used, keeping its values. A session that isn't there isn't created again,
+    /// it may have just been removed by a logout or moved to a new id by `Session::regenerate`.
+    ///
+    /// The default loads the session and saves it again, so a change saved in between is lost.
+    /// Stores that can change a single field in place should do that instead.
+    fn touch(&self, id: &str, last_seen: u64) -> io::Result<()> {
+        match self.load(id)? {
+            Some(record) => self.save(id, &SessionRecord { last_seen, ..record }),
+            None         => Ok(()),
+        }
+    }
+
+    /// Removes every session for which `expired` is true. Called about once a minute on one of the workers,
+    /// which doesn't handle requests until it returns, so a store that has to look at every session is only slow on that worker.
+    fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()>;
+
+    /// returns an id for a new session that can't be guessed: 32 random bytes from the operating system as base64url.
+    fn generate_id(&self) -> io::Result<String> {
+        let mut bytes = [0u8; 32];
+        getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+
+        Ok(base64url_encode(&bytes))
+    }
+}
+
+/// Keeps sessions in memory. They are lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        Ok(lock(&self.sessions).get(id).cloned())
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        lock(&self.sessions).insert(String::from(id), record.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        lock(&self.sessions).remove(id);
+        Ok(())
+    }
+
+    fn touch(&self, id: &str, last_seen: u64) -> io::Result<()> {
+        if let Some(record) = lock(&self.sessions).get_mut(id) {
+            record.last_seen = last_seen;
+        }
+
+        Ok(())
+    }
+
+    fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+        lock(&self.sessions).retain(|_, record| !expired(record));
+        Ok(())
+    }
+}
+
+/// A panicking handler can't leave the map half changed, every change is a single call, so a poisoned lock is still usable.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keeps every session in a JSON file of its own in a directory, so sessions survive restarts
+/// and can be shared by servers that see the same directory.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Uses `dir` for the session files, creating it if it doesn't exist.
+    /// On unix the files can only be read by the user the server runs as.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(FileStore { dir })
+    }
+
+    /// returns the file of a session, or None for an id that couldn't have been generated, as it comes from the client.
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        let valid = !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+
+        valid.then(|| self.dir.join(format!("{}.json", id)))
+    }
+
+    fn read(path: &PathBuf) -> io::Result<Option<SessionRecord>> {
+        let data = match fs::read(path) {
+            Ok(data)                                        => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound  => return Ok(None),
+            Err(e)                                          => return Err(e),
+        };
+
+        let record: Value = serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(Some(SessionRecord {
+            values:    record["values"].as_object().cloned().unwrap_or_default(),
+            created:   record["created"].as_u64().unwrap_or(0),
+            last_seen: record["last_seen"].as_u64().unwrap_or(0),
+        }))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        match self.path(id) {
+            Some(path) => FileStore::read(&path),
+            None       => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+
+        let path = self.path(id).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid session id"))?;
+
+        // every write has a temporary file of its own, workers saving the same session at once would mix their writes otherwise
+        let temp = path.with_extension(format!("{}-{}.tmp", std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed)));
+
+        let data = json!({ "values": record.values, "created": record.created, "last_seen": record.last_seen });
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        // written next to the session and renamed over it, so a session is never read half written
+        let written = options.open(&temp)
+                             .and_then(|mut file| file.write_all(data.to_string().as_bytes()))
+                             .and_then(|_| fs::rename(&temp, &path));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+
+        written
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match self.path(id).map(fs::remove_file) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _                                                   => Ok(()),
+        }
+    }
+
+    fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            // a file that can't be read is left alone, it may be being written
+            if let Ok(Some(record)) = FileStore::read(&path) {
+                if expired(&record) {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The SessionStore set on the builder. Stores don't have to implement Debug, the Config does.
+#[derive(Clone)]
+pub(crate) struct SharedStore(pub Arc<dyn SessionStore>);
+
+impl fmt::Debug for SharedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionStore")
+    }
+}
+
+/// The session cookie used when the builder isn't given one: the whole site, hidden from JavaScript, not sent by other sites.
+pub(crate) fn default_cookie() -> Cookie {
+    Cookie::new("session", "").path("/").http_only(true).same_site(SameSite::Lax)
+}
+
+/// Loads `request.session` before the handler runs and saves it after, in whichever way the builder chose.
+#[derive(Debug)]
+pub(crate) enum Sessions {
+    Cookie(CookieSessions),
+    Store(StoreSessions),
+}
+
+/// What `load` found, for `save`.
+#[derive(Debug, Default)]
+pub(crate) struct Loaded {
+    /// The id of the stored session the request came with.
+    id:      Option<String>,
+    created: u64,
+    /// When the stored session was last saved, to know if it has to be saved again to stay alive.
+    seen:    u64,
+}
+
+impl Sessions {
+    pub fn load(&self, request: &mut Request) -> Loaded {
+        match self {
+            Sessions::Cookie(sessions) => {
+                sessions.load(request);
+                Loaded::default()
+            },
+            Sessions::Store(sessions)  => sessions.load(request),
+        }
+    }
+
+    pub fn save(&self, request: &Request, response: &mut Response, loaded: Loaded) {
+        match self {
+            Sessions::Cookie(sessions) => sessions.save(request, response),
+            Sessions::Store(sessions)  => sessions.save(request, response, loaded),
+        }
+    }
+
+    /// true if expired sessions have to be purged now and then. Signed cookies expire on their own.
+    pub fn purges(&self) -> bool {
+        matches!(self, Sessions::Store(_))
+    }
+
+    /// Removes the expired sessions from the store. Called from a worker, away from the requests, every `PURGE_INTERVAL_SECS`.
+    pub fn purge(&self) {
+        if let Sessions::Store(sessions) = self {
+            sessions.purge();
+        }
+    }
+}
+
+/// Keeps `request.session` in a cookie signed with the secret key, so the client can read the session but can't change it.
+///
+/// The cookie is `payload.time.signature`: the values as base64url JSON, when it was signed in seconds since 1970,
+/// and the HMAC-SHA256 of both with the secret key. Cookies signed with a fallback key are still read and are signed again with the secret key.
+#[derive(Debug, Clone)]
+pub(crate) struct CookieSessions {
+    /// The secret key first, then the fallback keys. Empty when no secret key is set.
+    keys:   Vec<Vec<u8>>,
+    /// The name and attributes of the session cookie. Its Max-Age is also how long a signature stays valid.
+    cookie: Cookie,
+}
+
+impl CookieSessions {
+    pub fn new(secret_key: Option<Vec<u8>>, fallback_keys: Vec<Vec<u8>>, cookie: Cookie) -> CookieSessions {
+        let keys = match secret_key {
+            Some(key) => std::iter::once(key).chain(fallback_keys).collect(),
+            None      => Vec::new(),
+        };
+
+        CookieSessions { keys, cookie }
+    }
+
+    /// Fills `request.session` from the session cookie. A cookie that is missing, expired or badly signed gives an empty session.
+    pub fn load(&self, request: &mut Request) {
+        let value = match request.cookie(&self.cookie.name) {
+            Some(value) if !self.keys.is_empty() => value,
+            _                                    => return,
+        };
+
+        if let Some((values, key)) = self.verify(&value) {
+            request.session = Session::from_values(values);
+
+            // sent back signed with the current key, so the fallback key can be retired
+            if key > 0 {
+                request.session.mark_modified();
+            }
+        }
+    }
+
+    /// Sends the session back if the handler changed it. An emptied session removes the cookie.
+    pub fn save(&self, request: &Request, response: &mut Response) {
+        if !request.session.modified() {
+            return;
+        }
+
+        if self.keys.is_empty() {
+            if cfg!(debug_assertions) {
+                eprintln!("  * the session changed on {} {}, but it isn't saved without a secret_key", request.method, request.path);
+            }
+
+            return;
+        }
+
+        let had_cookie = request.cookie(&self.cookie.name).is_some();
+
+        if request.session.is_empty() {
+            if had_cookie {
+                response.set_cookie(self.cookie.clone().expire());
+            }
+
+            return;
+        }
+
+        let mut cookie = self.cookie.clone();
+        cookie.value = self.sign(&request.session.values());
+
+        if cookie.to_string().len() > MAX_COOKIE_SIZE && cfg!(debug_assertions) {
+            eprintln!("  * the session cookie is over {} bytes, browsers will drop it", MAX_COOKIE_SIZE);
+        }
+
+        response.set_cookie(cookie);
+        response.add_header("Vary", "Cookie");
+    }
+
+    fn sign(&self, values: &Map<String, Value>) -> String {
+        let payload = base64url_encode(Value::Object(values.clone()).to_string().as_bytes());
+        let signed = format!("{}.{}", payload, now());
+        let signature = base64url_encode(&hmac_sha256(&self.keys[0], signed.as_bytes()));
+
+        format!("{}.{}", signed, signature)
+    }
+
+    /// returns the values of a cookie and the index of the key that signed it, or None if no key did or it is too old.
+    fn verify(&self, value: &str) -> Option<(Map<String, Value>, usize)> {
+        let (signed, signature) = value.rsplit_once('.')?;
+        let (payload, time) = signed.split_once('.')?;
+        let signature = base64url_decode(signature)?;
+
+        let key = self.keys.iter().position(|key| verify_hmac_sha256(key, signed.as_bytes(), &signature))?;
+
+        let time: u64 = time.parse().ok()?;
+
+        if let Some(max_age) = self.cookie.max_age {
+            if now().saturating_sub(time) > max_age.as_secs() {
+                return None;
+            }
+        }
+
+        match serde_json::from_slice(&base64url_decode(payload)?).ok()? {
+            Value::Object(values) => Some((values, key)),
+            _                     => None,
+        }
+    }
+}
+
+/// Keeps `request.session` in a SessionStore, with its id in the session cookie.
+///
+/// A session expires when it hasn't been used for `idle`, or `lifetime` after it was created, whichever comes first.
+/// Requests that don't change the session still keep it alive, touching it in the store at most once every few seconds.
+#[derive(Debug)]
+pub(crate) struct StoreSessions {
+    store:      SharedStore,
+    cookie:     Cookie,
+    idle:       Duration,
+    lifetime:   Duration,
+    purging:    AtomicBool,
+}
+
+impl StoreSessions {
+    pub fn new(store: SharedStore, cookie: Cookie, idle: Duration, lifetime: Duration) -> StoreSessions {
+        StoreSessions { store, cookie, idle, lifetime, purging: AtomicBool::new(false) }
+    }
+
+    fn expired(&self, record: &SessionRecord, now: u64) -> bool {
+        now.saturating_sub(record.last_seen) > self.idle.as_secs() || now.saturating_sub(record.created) > self.lifetime.as_secs()
+    }
+
+    pub fn load(&self, request: &mut Request) -> Loaded {
+        let id = match request.cookie(&self.cookie.name) {
+            Some(id) => id,
+            None     => return Loaded::default(),
+        };
+
+        let record = match self.store.0.load(&id) {
+            Ok(Some(record)) => record,
+            Ok(None)         => return Loaded::default(),
+            Err(e)           => {
+                eprintln!("  * unable to load a session: {}", e);
+                return Loaded::default();
+            },
+        };
+
+        if self.expired(&record, now()) {
+            self.remove(&id);
+            return Loaded::default();
+        }
+
+        request.session = Session::from_values(record.values);
+
+        Loaded { id: Some(id), created: record.created, seen: record.last_seen }
+    }
+
+    pub fn save(&self, request: &Request, response: &mut Response, loaded: Loaded) {
+        let session = &request.session;
+        let now = now();
+
+        if session.is_empty() {
+            // an emptied session is removed, and an empty one that was never stored isn't worth storing
+            if let Some(id) = loaded.id.filter(|_| session.modified()) {
+                self.remove(&id);
+                response.set_cookie(self.cookie.clone().expire());
+            }
+
+            return;
+        }
+
+        if !session.modified() {
+            // only when it was last used is written, the values this request loaded may already be old
+            // if another request changed the session meanwhile
+            let refresh = (self.idle.as_secs() / 4).clamp(1, 60);
+
+            if let Some(id) = loaded.id.filter(|_| now.saturating_sub(loaded.seen) >= refresh) {
+                if let Err(e) = self.store.0.touch(&id, now) {
+                    eprintln!("  * unable to keep a session alive: {}", e);
+                }
+            }
+
+            return;
+        }
+
+        let (id, created) = match loaded.id {
+            Some(id) if !session.regenerated() => (id, loaded.created),
+            old                                => {
+                if let Some(old) = old {
+                    self.remove(&old);
+                }
+
+                match self.store.0.generate_id() {
+                    Ok(id) => {
+                        let mut cookie = self.cookie.clone();
+                        cookie.value = id.clone();
+
+                        response.set_cookie(cookie);
+                        response.add_header("Vary", "Cookie");
+
+                        (id, if loaded.created > 0 { loaded.created } else { now })
+                    },
+                    Err(e) => {
+                        eprintln!("  * unable to create a session id: {}", e);
+                        return;
+                    },
+                }
+            },
+        };
+
+        let record = SessionRecord { values: session.values(), created, last_seen: now };
+
+        if let Err(e) = self.store.0.save(&id, &record) {
+            eprintln!("  * unable to save a session: {}", e);
+        }
+    }
+
+    fn remove(&self, id: &str) {
+        if let Err(e) = self.store.0.remove(id) {
+            eprintln!("  * unable to remove a session: {}", e);
+        }
+    }
+
+    /// Removes the expired sessions from the store. A purge that is still running when the next one is due isn't started again.
+    fn purge(&self) {
+        if self.purging.swap(true, Ordering::Acquire) {
+            return;
+        }
+
+        let now = now();
+
+        if let Err(e) = self.store.0.purge(&|record| self.expired(record, now)) {
+            eprintln!("  * unable to remove expired sessions: {}", e);
+        }
+
+        self.purging.store(false, Ordering::Release);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_sessions(store: Arc<dyn SessionStore>) -> StoreSessions {
+        StoreSessions::new(SharedStore(store), default_cookie(), Duration::from_secs(1800), Duration::from_secs(8 * 3600))
+    }
+
+    fn record(values: Value, created: u64, last_seen: u64) -> SessionRecord {
+        SessionRecord { values: values.as_object().unwrap().clone(), created, last_seen }
+    }
+
+    fn request_with(id: &str) -> Request {
+        let mut request = Request::new();
+        request.headers.insert(String::from("cookie"), format!("session={}", id));
+        request
+    }
+
+    fn cookie_sessions(key: &str, fallbacks: &[&str], cookie: Cookie) -> CookieSessions {
+        CookieSessions::new(Some(key.as_bytes().to_vec()), fallbacks.iter().map(|key| key.as_bytes().to_vec()).collect(), cookie)
+    }
+
+    /// The value of the session cookie the response sets, if it sets one.
+    fn set_session(response: &Response) -> Option<String> {
+        response.headers.get_all("Set-Cookie").into_iter()
+                .find_map(|cookie| cookie.strip_prefix("session=").map(|rest| rest.split(';').next().unwrap().to_string()))
+    }
+
+    #[test]
+    fn signed_cookie_is_read_back() {
+        let sessions = cookie_sessions("secret", &[], default_cookie());
+
+        let request = Request::new();
+        request.session.insert("user", "someone");
+
+        let mut response = Response::new();
+        sessions.save(&request, &mut response);
+        let value = set_session(&response).unwrap();
+
+        let mut next = request_with(&value);
+        sessions.load(&mut next);
+
+        assert_eq!(next.session.get::<String>("user").as_deref(), Some("someone"));
+        assert!(!next.session.modified());
+    }
+
+    #[test]
+    fn tampered_cookie_gives_an_empty_session() {
+        let sessions = cookie_sessions("secret", &[], default_cookie());
+        let signed = sessions.sign(json!({ "user": "someone" }).as_object().unwrap());
+
+        let (_, rest) = signed.split_once('.').unwrap();
+        let forged = format!("{}.{}", base64url_encode(br#"{"user":"admin"}"#), rest);
+        let other_key = cookie_sessions("guessed", &[], default_cookie()).sign(json!({ "user": "admin" }).as_object().unwrap());
+
+        for value in [forged, other_key, format!("{}x", signed), String::from("garbage")] {
+            let mut request = request_with(&value);
+            sessions.load(&mut request);
+
+            assert!(request.session.is_empty(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn expired_signature_gives_an_empty_session() {
+        let sessions = cookie_sessions("secret", &[], default_cookie().max_age(Duration::from_secs(60)));
+
+        let payload = base64url_encode(br#"{"user":"someone"}"#);
+        let sign_at = |time: u64| {
+            let signed = format!("{}.{}", payload, time);
+            format!("{}.{}", signed, base64url_encode(&hmac_sha256(b"secret", signed.as_bytes())))
+        };
+
+        let mut fresh = request_with(&sign_at(now() - 30));
+        sessions.load(&mut fresh);
+        assert!(fresh.session.contains_key("user"));
+
+        let mut expired = request_with(&sign_at(now() - 120));
+        sessions.load(&mut expired);
+        assert!(expired.session.is_empty());
+    }
+
+    #[test]
+    fn fallback_key_is_signed_again_with_the_secret_key() {
+        let old = cookie_sessions("old", &[], default_cookie());
+        let rotated = cookie_sessions("new", &["old"], default_cookie());
+
+        let mut request = request_with(&old.sign(json!({ "user": "someone" }).as_object().unwrap()));
+        rotated.load(&mut request);
+
+        assert!(request.session.contains_key("user"));
+        assert!(request.session.modified());
+
+        let mut response = Response::new();
+        rotated.save(&request, &mut response);
+        let value = set_session(&response).unwrap();
+
+        // readable without the fallback key from now on
+        let mut next = request_with(&value);
+        cookie_sessions("new", &[], default_cookie()).load(&mut next);
+
+        assert_eq!(next.session.get::<String>("user").as_deref(), Some("someone"));
+    }
+
+    #[test]
+    fn file_store_saves_the_same_session_from_many_threads() {
+        let dir = std::env::temp_dir().join(format!("orangutan-session-test-{}", std::process::id()));
+        let store = Arc::new(FileStore::new(&dir).unwrap());
+
+        let threads: Vec<_> = (0..8).map(|thread| {
+            let store = store.clone();
+
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    let values = json!({ "thread": thread, "padding": "x".repeat(i * 40) });
+                    store.save("shared", &record(values, 1, 1)).unwrap();
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(store.load("shared").unwrap().is_some());
+
+        let left: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(left, vec![std::ffi::OsString::from("shared.json")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unchanged_session_only_refreshes_last_seen() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = store_sessions(store.clone());
+
+        store.save("abc", &record(json!({ "user": "old" }), now() - 600, now() - 600)).unwrap();
+
+        let mut request = request_with("abc");
+        let loaded = sessions.load(&mut request);
+
+        // another request logs in meanwhile
+        store.save("abc", &record(json!({ "user": "new" }), now() - 600, now())).unwrap();
+
+        sessions.save(&request, &mut Response::new(), loaded);
+
+        let saved = store.load("abc").unwrap().unwrap();
+        assert_eq!(saved.values["user"], "new");
+    }
+
+    #[test]
+    fn unchanged_session_isnt_recreated_after_removal() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = store_sessions(store.clone());
+
+        store.save("abc", &record(json!({ "user": "someone" }), now() - 600, now() - 600)).unwrap();
+
+        let mut request = request_with("abc");
+        let loaded = sessions.load(&mut request);
+
+        // another request regenerates the id meanwhile
+        store.remove("abc").unwrap();
+
+        sessions.save(&request, &mut Response::new(), loaded);
+
+        assert_eq!(store.load("abc").unwrap(), None);
+    }
+
+    #[test]
+    fn default_touch_keeps_values() {
+        let dir = std::env::temp_dir().join(format!("orangutan-touch-test-{}", std::process::id()));
+        let store = FileStore::new(&dir).unwrap();
+
+        store.save("abc", &record(json!({ "user": "someone" }), 5, 5)).unwrap();
+        store.touch("abc", 10).unwrap();
+        store.touch("missing", 10).unwrap();
+
+        assert_eq!(store.load("abc").unwrap(), Some(record(json!({ "user": "someone" }), 5, 10)));
+        assert_eq!(store.load("missing").unwrap(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expired_sessions_are_purged_apart_from_the_requests() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = Sessions::Store(store_sessions(store.clone()));
+
+        store.save("old", &record(json!({ "user": "gone" }), now() - 3600, now() - 3600)).unwrap();
+        store.save("new", &record(json!({ "user": "here" }), now(), now())).unwrap();
+
+        sessions.load(&mut request_with("new"));
+        assert!(store.load("old").unwrap().is_some());
+
+        sessions.purge();
+        assert_eq!(store.load("old").unwrap(), None);
+        assert!(store.load("new").unwrap().is_some());
+    }
+
+    #[test]
+    fn generated_ids_are_random() {
+        let store = MemoryStore::new();
+        let first = store.generate_id().unwrap();
+
+        assert_eq!(first.len(), 43);
+        assert_ne!(first, store.generate_id().unwrap());
+    }
+}
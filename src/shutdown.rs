@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the signal handler installed with `ShutdownHandle::on_signals`.
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+/// Stops a running Orangutan gracefully. Get one with `Orangutan::shutdown_handle` before calling `run`.
+///
+/// Once triggered the server stops accepting connections, lets the handlers that are already running finish,
+/// sends their responses and returns from `run`. Handlers still running after the shutdown timeout are abandoned.
+///
+/// ```no_run
+/// # use orangutan::Orangutan;
+/// let mut app = Orangutan::new("127.0.0.1:8080")?;
+/// let handle = app.shutdown_handle();
+///
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     handle.shutdown();
+/// });
+///
+/// app.run()?;
+/// # Ok::<(), orangutan::OrangutanError>(())
+/// ```
+
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle {
+    triggered: Arc<AtomicBool>,
+    signals:   Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    /// Asks the server to shut down. Can be called from any thread, any number of times.
+    pub fn shutdown(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+    }
+
+    /// returns true once a shutdown has been asked for, either with `shutdown` or by a signal.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst) || (self.signals.load(Ordering::SeqCst) && SIGNALED.load(Ordering::SeqCst))
+    }
+
+    /// Shuts the server down on SIGINT (CTRL+C) or SIGTERM. A second signal stops the process right away.
+    #[cfg(unix)]
+    pub fn on_signals(&self) {
+        self.signals.store(true, Ordering::SeqCst);
+
+        unsafe {
+            libc::signal(libc::SIGINT, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+
+    /// Signals are only handled on unix, elsewhere this does nothing.
+    #[cfg(not(unix))]
+    pub fn on_signals(&self) {}
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if SIGNALED.swap(true, Ordering::SeqCst) {
+        // only async-signal-safe calls are allowed in here
+        unsafe { libc::_exit(128 + signal) };
+    }
+}
@@ -0,0 +1,56 @@
+//! HMAC-SHA256 and base64url, for the signed session cookies.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The HMAC-SHA256 of `data` with `key`.
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Checks `signature` against the HMAC-SHA256 of `data` with `key`, in a time that doesn't depend on where the first difference is,
+/// so a signature can't be guessed byte by byte.
+pub(crate) fn verify_hmac_sha256(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Encodes as base64url without padding, which can go in a cookie as it is.
+pub(crate) fn base64url_encode(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+/// Decodes base64url without padding, or None if `encoded` isn't that. Only the encoding `base64url_encode` gives is accepted,
+/// so every value has a single encoding: padding is refused and the bits of the last character that aren't part of a byte have to be zero.
+pub(crate) fn base64url_decode(encoded: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(encoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64url_rejects_what_it_wouldnt_encode() {
+        for encoded in ["Zh", "Zm9", "Zm9vYh", "Z", "Zm9vY", "Zm9v+g", "Zm9v/g", "Zg==", "Zm 9v"] {
+            assert_eq!(base64url_decode(encoded), None, "{:?}", encoded);
+        }
+    }
+
+    #[test]
+    fn signature_is_checked_against_the_key_and_data() {
+        let signature = hmac_sha256(b"secret", b"data");
+
+        assert!(verify_hmac_sha256(b"secret", b"data", &signature));
+        assert!(!verify_hmac_sha256(b"other", b"data", &signature));
+        assert!(!verify_hmac_sha256(b"secret", b"date", &signature));
+        assert!(!verify_hmac_sha256(b"secret", b"data", &signature[..31]));
+    }
+}
@@ -0,0 +1,50 @@
+use lib_shared::request::{ParamError, Request};
+
+fn with_query(query: &str) -> Request {
+    let mut request = Request::new();
+    request.query = Some(String::from(query));
+    request
+}
+
+#[test]
+fn invalid_arg_falls_back_to_the_default() {
+    let request = with_query("page=two&size=10");
+
+    assert_eq!(request.arg::<u32>("page").unwrap_or(1), 1);
+    assert_eq!(request.arg::<u32>("size"), Some(10));
+    assert_eq!(request.arg::<u32>("missing"), None);
+}
+
+#[test]
+fn try_arg_tells_missing_and_invalid_apart() {
+    let request = with_query("page=two");
+
+    assert!(matches!(request.try_arg::<u32>("page"), Err(ParamError::Invalid { .. })));
+    assert!(matches!(request.try_arg::<u32>("missing"), Err(ParamError::Missing(_))));
+}
+
+#[test]
+fn invalid_field_is_none() {
+    let mut request = Request::new();
+    request.headers.insert(String::from("content-type"), String::from("application/x-www-form-urlencoded"));
+    request.body = b"age=old&name=someone".to_vec();
+
+    assert_eq!(request.field::<u8>("age"), None);
+    assert_eq!(request.field::<String>("name").as_deref(), Some("someone"));
+}
+
+#[test]
+fn try_values_return_the_first_invalid_value() {
+    let request = with_query("tag=1&tag=x&tag=y");
+
+    assert_eq!(request.try_arg_values::<String>("tag"), Ok(vec![String::from("1"), String::from("x"), String::from("y")]));
+    assert_eq!(request.try_arg_values::<u32>("missing"), Ok(Vec::new()));
+    assert!(matches!(request.try_arg_values::<u32>("tag"), Err(ParamError::Invalid { value, .. }) if value == "x"));
+
+    let mut request = Request::new();
+    request.headers.insert(String::from("content-type"), String::from("application/x-www-form-urlencoded"));
+    request.body = b"topic=2&topic=three".to_vec();
+
+    assert!(matches!(request.try_field_values::<u8>("topic"), Err(ParamError::Invalid { value, .. }) if value == "three"));
+    assert_eq!(request.try_field_values::<u8>("other"), Ok(Vec::new()));
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use lib_shared::request::Request;
+use lib_shared::response::Response;
+use lib_shared::ROUTES;
+use orangutan_macro::route;
+
+// named like the wrapper the macro used to generate, which then called itself
+#[route(path="/handle/<uint:id>", method="[GET]")]
+fn handle(id: u32, _request: &Request) -> Response {
+    let mut res = Response::new();
+    res.insert(format!("handled {}", id));
+    res
+}
+
+#[route(path="/register", method="[GET]")]
+fn __orangutan_register_route() -> Response {
+    let mut res = Response::new();
+    res.insert("registered");
+    res
+}
+
+/// Calls the registered handler of `path` with the given route variables.
+fn call(path: &str, params: &[(&str, &str)]) -> Response {
+    let params: HashMap<String, String> = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let handler = ROUTES.lock().unwrap().iter().find(|route| route.path == path).map(|route| route.handler).unwrap();
+
+    handler(&Request::new().with_params(params))
+}
+
+#[test]
+fn handler_named_handle_is_called() {
+    let res = call("/handle/<uint:id>", &[("id", "7")]);
+
+    assert_eq!(res.payload, b"handled 7");
+}
+
+#[test]
+fn handler_named_like_the_registration_is_called() {
+    let res = call("/register", &[]);
+
+    assert_eq!(res.payload, b"registered");
+}
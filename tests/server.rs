@@ -0,0 +1,301 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use lib_shared::request::Request;
+use lib_shared::response::Response;
+use orangutan::{Orangutan, OrangutanBuilder};
+use orangutan_macro::route;
+
+#[route(path="/fields", method="[POST]")]
+fn fields(request: &Request) -> Response {
+    let mut res = Response::new();
+    res.insert(format!("header={:?} trailer={:?} body={:?}",
+                       request.headers.get("cookie"),
+                       request.trailers.get("cookie"),
+                       String::from_utf8_lossy(&request.body)));
+    res
+}
+
+#[route(path="/hello", method="[GET]")]
+fn hello() -> Response {
+    let mut res = Response::new();
+    res.insert("hello");
+    res
+}
+
+#[route(path="/stream", method="[GET]")]
+fn stream() -> Response {
+    Response::from_chunks(vec![b"hello ".to_vec(), b"streamed world".to_vec()])
+}
+
+#[route(path="/slow", method="[GET]")]
+fn slow() -> Response {
+    thread::sleep(Duration::from_millis(300));
+
+    let mut res = Response::new();
+    res.insert("finished");
+    res
+}
+
+#[route(path="/panic", method="[GET]")]
+fn panics() -> Response {
+    panic!("handler failed on purpose");
+}
+
+/// Runs a server on a free port until the test process exits, and returns its address.
+fn serve() -> String {
+    serve_with(|builder| builder)
+}
+
+/// Runs a server like `serve`, configured by `configure`.
+fn serve_with(configure: fn(OrangutanBuilder) -> OrangutanBuilder) -> String {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("127.0.0.1:{}", port);
+    let (ready, started) = mpsc::channel();
+
+    let bind = address.clone();
+    thread::spawn(move || {
+        let mut app = configure(Orangutan::builder().bind(bind).workers(2)).build().unwrap();
+        ready.send(()).unwrap();
+        app.run().unwrap();
+    });
+
+    started.recv().unwrap();
+    address
+}
+
+/// Connects to the server, giving up on reads after a few seconds so a missing response fails the test instead of hanging it.
+fn connect(address: &str) -> TcpStream {
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+}
+
+/// Reads a single response with a Content-Length from the connection, leaving whatever follows it.
+fn read_response(stream: &mut TcpStream) -> String {
+    let mut answer = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while !answer.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        answer.push(byte[0]);
+    }
+
+    let head = String::from_utf8_lossy(&answer).into_owned();
+    let length: usize = head.lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .map_or(0, |length| length.parse().unwrap());
+
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body).unwrap();
+
+    head + &String::from_utf8_lossy(&body)
+}
+
+/// Sends `request` on a connection of its own and returns everything the server answered.
+fn send(address: &str, request: &[u8]) -> String {
+    let mut stream = connect(address);
+    stream.write_all(request).unwrap();
+
+    let mut answer = Vec::new();
+    let _ = stream.read_to_end(&mut answer);
+
+    String::from_utf8_lossy(&answer).into_owned()
+}
+
+#[test]
+fn trailers_dont_become_headers() {
+    let address = serve();
+
+    let answer = send(&address, b"POST /fields HTTP/1.1\r\nHost: x\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                  5\r\nhello\r\n0\r\nCookie: session=planted\r\n\r\n");
+
+    assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+    assert!(answer.contains(r#"header=None trailer=Some("session=planted") body="hello""#), "{}", answer);
+}
+
+#[test]
+fn request_before_a_half_close_is_answered() {
+    let address = serve();
+
+    let mut stream = connect(&address);
+    stream.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\nhi\
+                       POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nbye").unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let mut answer = Vec::new();
+    stream.read_to_end(&mut answer).unwrap();
+    let answer = String::from_utf8_lossy(&answer);
+
+    assert_eq!(answer.matches("HTTP/1.1 200").count(), 2, "{}", answer);
+    assert!(answer.contains(r#"body="hi""#), "{}", answer);
+    assert!(answer.contains(r#"body="bye""#), "{}", answer);
+}
+
+#[test]
+fn connection_is_kept_alive_between_requests() {
+    let address = serve();
+    let mut stream = connect(&address);
+
+    stream.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\none").unwrap();
+    let first = read_response(&mut stream);
+
+    stream.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\ntwo").unwrap();
+    let second = read_response(&mut stream);
+
+    assert!(first.starts_with("HTTP/1.1 200") && first.ends_with(r#"body="one""#), "{}", first);
+    assert!(second.starts_with("HTTP/1.1 200") && second.ends_with(r#"body="two""#), "{}", second);
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let address = serve();
+    let mut stream = connect(&address);
+
+    stream.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\none\
+                       POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\ntwo\
+                       POST /fields HTTP/1.1\r\nHost: x\r\nConnection: close\r\nContent-Length: 5\r\n\r\nthree").unwrap();
+
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).unwrap();
+
+    let order: Vec<Option<usize>> = ["one", "two", "three"].iter().map(|body| answer.find(&format!("body={:?}", body))).collect();
+
+    assert_eq!(answer.matches("HTTP/1.1 200").count(), 3, "{}", answer);
+    assert!(order.iter().all(Option::is_some) && order.windows(2).all(|pair| pair[0] < pair[1]), "{}", answer);
+}
+
+#[test]
+fn streamed_response_is_chunked_and_keeps_the_connection() {
+    let address = serve();
+
+    let answer = send(&address, b"GET /stream HTTP/1.1\r\nHost: x\r\n\r\n\
+                                  POST /fields HTTP/1.1\r\nHost: x\r\nConnection: close\r\nContent-Length: 4\r\n\r\nnext");
+    let (first, next) = answer.split_once("0\r\n\r\n").unwrap_or_else(|| panic!("{}", answer));
+    let (head, body) = first.split_once("\r\n\r\n").unwrap();
+
+    assert!(head.starts_with("HTTP/1.1 200") && head.contains("\r\nTransfer-Encoding: chunked"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+    assert_eq!(body, "6\r\nhello \r\nE\r\nstreamed world\r\n");
+    assert!(next.starts_with("HTTP/1.1 200") && next.ends_with(r#"body="next""#), "{}", next);
+}
+
+#[test]
+fn streamed_response_to_http_1_0_ends_with_the_connection() {
+    let address = serve();
+
+    let answer = send(&address, b"GET /stream HTTP/1.0\r\n\r\n");
+    let (head, body) = answer.split_once("\r\n\r\n").unwrap();
+
+    assert!(head.starts_with("HTTP/1.1 200") && !head.contains("Transfer-Encoding"), "{}", head);
+    assert_eq!(body, "hello streamed world");
+}
+
+#[test]
+fn shutdown_handle_lets_running_handlers_finish_and_run_return() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("127.0.0.1:{}", port);
+
+    let mut app = Orangutan::builder().bind(&address).workers(2).build().unwrap();
+    let handle = app.shutdown_handle();
+    let (done, returned) = mpsc::channel();
+
+    thread::spawn(move || done.send(app.run().is_ok()).unwrap());
+
+    let mut idle = connect(&address);
+    idle.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 0\r\n\r\n").unwrap();
+    read_response(&mut idle);
+
+    let mut busy = connect(&address);
+    busy.write_all(b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    handle.shutdown();
+
+    let mut answer = String::new();
+    busy.read_to_string(&mut answer).unwrap();
+    assert!(answer.starts_with("HTTP/1.1 200") && answer.ends_with("finished"), "{}", answer);
+
+    assert_eq!(idle.read(&mut [0u8; 1]).unwrap(), 0);
+    assert_eq!(returned.recv_timeout(Duration::from_secs(5)), Ok(true));
+    assert!(TcpStream::connect(&address).is_err());
+}
+
+#[test]
+fn slow_head_is_answered_with_408() {
+    let address = serve_with(|builder| builder.header_timeout(Duration::from_millis(200)));
+
+    let answer = send(&address, b"GET /stream HTTP/1.1\r\nHost: x\r\n");
+    assert!(answer.starts_with("HTTP/1.1 408") && answer.contains("\r\nConnection: close\r\n"), "{}", answer);
+
+    // a connection that never sent anything is closed without an answer
+    assert_eq!(send(&address, b""), "");
+}
+
+#[test]
+fn full_server_answers_503_with_retry_after() {
+    let address = serve_with(|builder| builder.max_connections(1));
+
+    let mut first = connect(&address);
+    first.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 0\r\n\r\n").unwrap();
+    assert!(read_response(&mut first).starts_with("HTTP/1.1 200"));
+
+    let answer = send(&address, b"GET /stream HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert!(answer.starts_with("HTTP/1.1 503"), "{}", answer);
+    assert!(answer.contains("\r\nRetry-After: 1\r\n") && answer.contains("\r\nConnection: close\r\n"), "{}", answer);
+
+    // the slot is free again once the first connection is closed
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+
+    let answer = send(&address, b"POST /fields HTTP/1.1\r\nHost: x\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+    assert!(answer.starts_with("HTTP/1.1 200"), "{}", answer);
+}
+
+#[test]
+fn panicking_handler_is_answered_with_500() {
+    let address = serve();
+    let mut stream = connect(&address);
+
+    // more panics than there are workers
+    for _ in 0..3 {
+        stream.write_all(b"GET /panic HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        let answer = read_response(&mut stream);
+        assert!(answer.starts_with("HTTP/1.1 500"), "{}", answer);
+    }
+
+    // neither the connection nor the workers went down with the handler
+    stream.write_all(b"POST /fields HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\nok").unwrap();
+    let answer = read_response(&mut stream);
+    assert!(answer.starts_with("HTTP/1.1 200") && answer.ends_with(r#"body="ok""#), "{}", answer);
+}
+
+#[test]
+fn head_is_answered_by_get_without_the_body() {
+    let address = serve();
+
+    let answer = send(&address, b"HEAD /hello HTTP/1.1\r\nHost: x\r\n\r\n\
+                                  GET /hello HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    let (head, rest) = answer.split_once("\r\n\r\n").unwrap();
+
+    assert!(head.starts_with("HTTP/1.1 200") && head.contains("\r\nContent-Length: 5"), "{}", head);
+    // the next response follows the head right away
+    assert!(rest.starts_with("HTTP/1.1 200") && rest.ends_with("\r\n\r\nhello"), "{}", rest);
+}
+
+#[test]
+fn wrong_method_is_405_with_allow_and_options_is_answered() {
+    let address = serve();
+
+    let wrong = send(&address, b"DELETE /fields HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    assert!(wrong.starts_with("HTTP/1.1 405") && wrong.contains("\r\nAllow: OPTIONS, POST\r\n"), "{}", wrong);
+
+    let options = send(&address, b"OPTIONS /hello HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    assert!(options.starts_with("HTTP/1.1 200") && options.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"), "{}", options);
+
+    let missing = send(&address, b"DELETE /nowhere HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    assert!(missing.starts_with("HTTP/1.1 404") && !missing.contains("Allow:"), "{}", missing);
+}